@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE admins (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL UNIQUE,
+    password VARCHAR NOT NULL,
+    created TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC')
+);
+
+CREATE TABLE sessions (
+    token VARCHAR PRIMARY KEY,
+    aid INT REFERENCES admins(id) ON DELETE CASCADE NOT NULL,
+    created TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC'),
+    expires TIMESTAMP WITHOUT TIME ZONE NOT NULL
+)
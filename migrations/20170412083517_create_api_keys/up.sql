@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE api_keys (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    key VARCHAR NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    created TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC'),
+    expires TIMESTAMP WITHOUT TIME ZONE,
+    last_used TIMESTAMP WITHOUT TIME ZONE
+)
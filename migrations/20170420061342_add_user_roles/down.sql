@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE posts DROP COLUMN uid;
+
+ALTER TABLE sessions RENAME COLUMN uid TO aid;
+
+DELETE FROM users WHERE role <> 'admin';
+ALTER TABLE users DROP COLUMN role;
+ALTER TABLE users RENAME TO admins
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE admins RENAME TO users;
+ALTER TABLE users ADD COLUMN role VARCHAR NOT NULL DEFAULT 'author'
+    CHECK (role IN ('admin', 'editor', 'author', 'moderator'));
+UPDATE users SET role = 'admin';
+
+ALTER TABLE sessions RENAME COLUMN aid TO uid;
+
+ALTER TABLE posts ADD COLUMN uid INT REFERENCES users(id) ON DELETE SET NULL
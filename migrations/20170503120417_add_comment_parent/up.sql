@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE comments ADD COLUMN parent_id INT REFERENCES comments(id);
+CREATE INDEX comments_parent_id_idx ON comments (parent_id)
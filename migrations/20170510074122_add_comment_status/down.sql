@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE settings;
+
+ALTER TABLE comments ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT 'f';
+UPDATE comments SET deleted = 't' WHERE status <> 'approved';
+ALTER TABLE comments DROP COLUMN status
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE comments ADD COLUMN status VARCHAR NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'approved', 'spam', 'rejected', 'deleted'));
+UPDATE comments SET status = CASE WHEN deleted THEN 'deleted' ELSE 'approved' END;
+ALTER TABLE comments DROP COLUMN deleted;
+CREATE INDEX comments_status_idx ON comments (status);
+
+CREATE TABLE settings (
+    key VARCHAR PRIMARY KEY,
+    value VARCHAR NOT NULL
+);
+INSERT INTO settings (key, value) VALUES ('moderation', 'first_time')
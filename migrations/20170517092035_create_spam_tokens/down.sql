@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE comments DROP COLUMN trained;
+ALTER TABLE comments DROP COLUMN spam_score;
+
+DROP TABLE spam_corpus;
+DROP TABLE spam_tokens
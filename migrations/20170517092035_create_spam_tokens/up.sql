@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE spam_tokens (
+    token VARCHAR PRIMARY KEY,
+    spam INT NOT NULL DEFAULT 0,
+    ham INT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE spam_corpus (
+    label VARCHAR PRIMARY KEY CHECK (label IN ('spam', 'ham')),
+    documents INT NOT NULL DEFAULT 0
+);
+INSERT INTO spam_corpus (label) VALUES ('spam'), ('ham');
+
+ALTER TABLE comments ADD COLUMN spam_score DOUBLE PRECISION;
+ALTER TABLE comments ADD COLUMN trained VARCHAR CHECK (trained IN ('spam', 'ham'))
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE rate_limits (
+    key VARCHAR PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated TIMESTAMP WITHOUT TIME ZONE NOT NULL
+)
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TEMPORARY TABLE visitor_merges AS
+    SELECT v.id AS old_id, keep.id AS new_id
+    FROM visitors v
+    JOIN (SELECT lower(trim(mail)) AS mail, min(id) AS id FROM visitors GROUP BY 1) keep
+        ON lower(trim(v.mail)) = keep.mail AND v.id <> keep.id;
+UPDATE comments SET vid = m.new_id FROM visitor_merges m WHERE comments.vid = m.old_id;
+DELETE FROM visitors USING visitor_merges m WHERE visitors.id = m.old_id;
+DROP TABLE visitor_merges;
+
+UPDATE visitors SET mail = trim(mail);
+CREATE UNIQUE INDEX visitors_mail_key ON visitors (lower(trim(mail)))
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE visitors ADD COLUMN verified_at TIMESTAMP WITHOUT TIME ZONE
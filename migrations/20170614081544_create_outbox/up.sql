@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE outbox (
+    id SERIAL PRIMARY KEY,
+    recipient VARCHAR NOT NULL,
+    subject VARCHAR NOT NULL,
+    body TEXT NOT NULL,
+    created TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC'),
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC'),
+    last_error TEXT,
+    sent_at TIMESTAMP WITHOUT TIME ZONE
+);
+CREATE INDEX outbox_due_idx ON outbox (next_attempt) WHERE sent_at IS NULL
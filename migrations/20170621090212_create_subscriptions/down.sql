@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE comments DROP COLUMN notified;
+DROP TABLE subscriptions
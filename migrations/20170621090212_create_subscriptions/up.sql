@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE subscriptions (
+    vid INT REFERENCES visitors(id) ON DELETE CASCADE NOT NULL,
+    pid INT REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    created TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC'),
+    PRIMARY KEY (vid, pid)
+);
+CREATE INDEX subscriptions_pid_idx ON subscriptions (pid);
+ALTER TABLE comments ADD COLUMN notified BOOLEAN NOT NULL DEFAULT 'f';
+UPDATE comments SET notified = 't' WHERE status = 'approved'
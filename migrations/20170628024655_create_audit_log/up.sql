@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE audit_log (
+    id SERIAL PRIMARY KEY,
+    uid INT REFERENCES users(id) ON DELETE SET NULL,
+    action VARCHAR NOT NULL,
+    subject VARCHAR NOT NULL,
+    detail TEXT NOT NULL DEFAULT '',
+    created TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC')
+);
+CREATE INDEX audit_log_subject_idx ON audit_log (subject)
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE visitors ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT 'f'
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE posts ADD COLUMN deleted_at TIMESTAMP WITHOUT TIME ZONE;
+UPDATE posts SET deleted_at = (NOW() AT TIME ZONE 'UTC') WHERE deleted;
+
+ALTER TABLE comments ADD COLUMN deleted_at TIMESTAMP WITHOUT TIME ZONE;
+ALTER TABLE comments ADD COLUMN prior_status VARCHAR;
+UPDATE comments SET deleted_at = (NOW() AT TIME ZONE 'UTC') WHERE status = 'deleted'
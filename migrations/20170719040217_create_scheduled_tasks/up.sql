@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE scheduled_tasks (
+    name VARCHAR PRIMARY KEY,
+    last_started TIMESTAMP WITHOUT TIME ZONE,
+    last_finished TIMESTAMP WITHOUT TIME ZONE,
+    last_result VARCHAR CHECK (last_result IN ('ok', 'error')),
+    last_message TEXT,
+    runs INT NOT NULL DEFAULT 0
+)
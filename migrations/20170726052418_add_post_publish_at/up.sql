@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE posts ADD COLUMN publish_at TIMESTAMP WITHOUT TIME ZONE;
+ALTER TABLE posts ADD COLUMN published_at TIMESTAMP WITHOUT TIME ZONE;
+UPDATE posts SET publish_at = created, published_at = created WHERE published
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE post_transitions;
+ALTER TABLE posts ADD COLUMN published BOOLEAN NOT NULL DEFAULT 'f';
+UPDATE posts SET published = 't' WHERE state IN ('scheduled', 'published');
+ALTER TABLE posts DROP COLUMN state
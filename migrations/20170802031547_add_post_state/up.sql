@@ -0,0 +1,16 @@
+-- Your SQL goes here
+ALTER TABLE posts ADD COLUMN state VARCHAR NOT NULL DEFAULT 'draft'
+    CHECK (state IN ('draft', 'review', 'scheduled', 'published', 'unpublished', 'archived'));
+UPDATE posts SET state = 'published' WHERE published AND published_at IS NOT NULL;
+UPDATE posts SET state = 'scheduled' WHERE published AND published_at IS NULL;
+ALTER TABLE posts DROP COLUMN published;
+
+CREATE TABLE post_transitions (
+    id SERIAL PRIMARY KEY,
+    pid INT REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    from_state VARCHAR NOT NULL,
+    to_state VARCHAR NOT NULL,
+    uid INT REFERENCES users(id) ON DELETE SET NULL,
+    created TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC')
+);
+CREATE INDEX post_transitions_pid_idx ON post_transitions (pid)
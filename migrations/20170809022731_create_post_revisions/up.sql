@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE post_revisions (
+    id SERIAL PRIMARY KEY,
+    pid INT REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    rev INT NOT NULL,
+    title VARCHAR NOT NULL,
+    category VARCHAR NOT NULL,
+    body TEXT NOT NULL,
+    uid INT REFERENCES users(id) ON DELETE SET NULL,
+    created TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC'),
+    UNIQUE (pid, rev)
+);
+INSERT INTO post_revisions (pid, rev, title, category, body, uid, created)
+    SELECT id, 1, title, category, body, uid, last_edited FROM posts
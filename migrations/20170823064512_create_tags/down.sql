@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE post_revisions ADD COLUMN category VARCHAR NOT NULL DEFAULT '';
+UPDATE post_revisions SET category = array_to_string(categories, ',');
+ALTER TABLE post_revisions DROP COLUMN categories;
+
+ALTER TABLE posts ADD COLUMN category VARCHAR NOT NULL DEFAULT '';
+UPDATE posts SET category = coalesce((
+    SELECT string_agg(t.name, ',' ORDER BY pt.position)
+    FROM post_tags pt JOIN tags t ON t.id = pt.tid WHERE pt.pid = posts.id), '');
+DROP TABLE post_tags;
+DROP TABLE tags
@@ -0,0 +1,35 @@
+-- Your SQL goes here
+CREATE TABLE tags (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    slug VARCHAR NOT NULL UNIQUE,
+    created TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC')
+);
+CREATE TABLE post_tags (
+    pid INT REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    tid INT REFERENCES tags(id) ON DELETE CASCADE NOT NULL,
+    position INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (pid, tid)
+);
+CREATE INDEX post_tags_tid_idx ON post_tags (tid);
+
+-- Slugs as db::tag::slugify makes them: '+' and '#' become the words 'plus'
+-- and 'sharp', runs of other ASCII characters than letters and digits
+-- become '-', ASCII letters are lowercased.
+CREATE TEMPORARY TABLE category_tags AS
+    SELECT p.id AS pid, trim(c.name) AS name, c.position,
+           trim(both '-' from translate(regexp_replace(replace(replace(c.name, '+', '-plus-'), '#', '-sharp-'),
+                                                       '[^A-Za-z0-9\u0080-\U0010FFFF]+', '-', 'g'),
+                                        'ABCDEFGHIJKLMNOPQRSTUVWXYZ', 'abcdefghijklmnopqrstuvwxyz')) AS slug
+    FROM posts p, unnest(string_to_array(p.category, ',')) WITH ORDINALITY AS c (name, position);
+INSERT INTO tags (name, slug)
+    SELECT DISTINCT ON (slug) name, slug FROM category_tags WHERE slug <> '' ORDER BY slug, name;
+INSERT INTO post_tags (pid, tid, position)
+    SELECT c.pid, t.id, min(c.position) FROM category_tags c JOIN tags t ON t.slug = c.slug GROUP BY c.pid, t.id;
+DROP TABLE category_tags;
+ALTER TABLE posts DROP COLUMN category;
+
+ALTER TABLE post_revisions ADD COLUMN categories VARCHAR[] NOT NULL DEFAULT '{}';
+UPDATE post_revisions SET categories = ARRAY(
+    SELECT trim(c) FROM unnest(string_to_array(category, ',')) AS c WHERE trim(c) <> '');
+ALTER TABLE post_revisions DROP COLUMN category
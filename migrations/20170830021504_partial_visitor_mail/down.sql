@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+UPDATE visitors SET mail = 'deleted-' || id || '@invalid'
+WHERE deleted AND EXISTS (
+    SELECT 1 FROM visitors o
+    WHERE o.id <> visitors.id AND lower(trim(o.mail)) = lower(trim(visitors.mail))
+        AND (NOT o.deleted OR o.id < visitors.id));
+DROP INDEX visitors_mail_key;
+CREATE UNIQUE INDEX visitors_mail_key ON visitors (lower(trim(mail)))
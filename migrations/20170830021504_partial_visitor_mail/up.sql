@@ -0,0 +1,3 @@
+-- Your SQL goes here
+DROP INDEX visitors_mail_key;
+CREATE UNIQUE INDEX visitors_mail_key ON visitors (lower(trim(mail))) WHERE NOT deleted
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+UPDATE tags SET slug = s.slug
+FROM (SELECT id, trim(both '-' from translate(regexp_replace(name, '[^A-Za-z0-9\u0080-\U0010FFFF]+', '-', 'g'),
+                                              'ABCDEFGHIJKLMNOPQRSTUVWXYZ', 'abcdefghijklmnopqrstuvwxyz')) AS slug
+      FROM tags WHERE name LIKE '%+%' OR name LIKE '%#%') s
+WHERE tags.id = s.id AND s.slug <> '' AND NOT EXISTS (SELECT 1 FROM tags o WHERE o.slug = s.slug)
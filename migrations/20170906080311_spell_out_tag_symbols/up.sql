@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- Slugs of names with '+' or '#' as db::tag::slugify now makes them; tags
+-- already folded together by the old slugs cannot be told apart again.
+UPDATE tags SET slug = s.slug
+FROM (SELECT id, trim(both '-' from translate(regexp_replace(replace(replace(name, '+', '-plus-'), '#', '-sharp-'),
+                                                             '[^A-Za-z0-9\u0080-\U0010FFFF]+', '-', 'g'),
+                                              'ABCDEFGHIJKLMNOPQRSTUVWXYZ', 'abcdefghijklmnopqrstuvwxyz')) AS slug
+      FROM tags WHERE name LIKE '%+%' OR name LIKE '%#%') s
+WHERE tags.id = s.id AND s.slug <> '' AND NOT EXISTS (SELECT 1 FROM tags o WHERE o.slug = s.slug)
@@ -0,0 +1,319 @@
+// Hashing
+use crypto::digest::Digest;
+use crypto::scrypt::{scrypt_simple, scrypt_check, ScryptParams};
+use crypto::sha2::Sha256;
+use rand::{OsRng, Rng};
+
+// Environment
+use dotenv::dotenv;
+use std::env;
+
+// Timestamp
+use chrono::prelude::*;
+use chrono::Duration;
+
+// Provides user identity for Rocket
+use rocket::request::{Outcome, FromRequest};
+use rocket::Outcome::{Success, Failure, Forward};
+use rocket::http::Status;
+use rocket::Request;
+
+use std::marker::PhantomData;
+
+use models::{self, Post};
+use db::{DB_POOL, DBResult, Error, user, api_key};
+use db::post::State;
+
+
+pub const SESSION_COOKIE: &'static str = "session";
+/// Prefix that tells API keys apart from session tokens.
+pub const API_KEY_PREFIX: &'static str = "key_";
+const TOKEN_LENGTH: usize = 40;
+const SESSION_DAYS: i64 = 7;
+
+
+pub fn hash_password(password: &str) -> String {
+    let params = ScryptParams::new(14, 8, 1);
+    scrypt_simple(password, &params).expect("Failed to hash password.")
+}
+
+
+pub fn verify_password(password: &str, hashed: &str) -> bool {
+    scrypt_check(password, hashed).unwrap_or(false)
+}
+
+
+lazy_static! {
+    static ref DUMMY_HASH: String = hash_password("dummy password");
+}
+
+/// Spends the time of a password check for a user that does not exist, so
+/// failed logins take as long whether the name is known or not.
+pub fn reject_password(password: &str) {
+    verify_password(password, &DUMMY_HASH);
+}
+
+
+/// Generates a random token; only its hash should ever be stored.
+pub fn generate_token() -> String {
+    let mut rng = OsRng::new().expect("Failed to access OS random source.");
+    rng.gen_ascii_chars().take(TOKEN_LENGTH).collect()
+}
+
+
+pub fn generate_api_key() -> String {
+    format!("{}{}", API_KEY_PREFIX, generate_token())
+}
+
+
+pub fn hash_token(token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input_str(token);
+    hasher.result_str()
+}
+
+
+pub fn session_expiry() -> NaiveDateTime {
+    UTC::now().naive_utc() + Duration::days(SESSION_DAYS)
+}
+
+
+/// Creates the first admin from `ADMIN_NAME`/`ADMIN_PASSWORD` when none exists.
+pub fn bootstrap_admin() {
+    dotenv().ok();
+
+    let (name, password) = match (env::var("ADMIN_NAME"), env::var("ADMIN_PASSWORD")) {
+        (Ok(name), Ok(password)) => (name, password),
+        _ => return,
+    };
+    let conn = DB_POOL.get().expect("Failed to get db connection.");
+    if user::count_role(&*conn, Role::Admin.as_str()).expect("Failed to count admins.") == 0 {
+        user::create(&*conn, &name, &hash_password(&password), Role::Admin.as_str())
+            .expect("Failed to create admin.");
+    }
+}
+
+
+/// Reads a session token from `Authorization: Bearer` or the session cookie.
+fn request_token(request: &Request) -> Option<String> {
+    if let Some(header) = request.headers().get_one("Authorization") {
+        if header.starts_with("Bearer ") {
+            return Some(header["Bearer ".len()..].trim().into());
+        }
+    }
+    request.cookies().find(SESSION_COOKIE).map(|c| c.value().into())
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Admin,
+    Editor,
+    Author,
+    Moderator,
+}
+
+impl Role {
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "admin" => Some(Role::Admin),
+            "editor" => Some(Role::Editor),
+            "author" => Some(Role::Author),
+            "moderator" => Some(Role::Moderator),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Role::Admin => "admin",
+            Role::Editor => "editor",
+            Role::Author => "author",
+            Role::Moderator => "moderator",
+        }
+    }
+
+    /// Scopes every user with this role holds.
+    pub fn scopes(&self) -> &'static [&'static str] {
+        match *self {
+            Role::Admin => SCOPES,
+            Role::Editor => &["post:write", "post:publish"],
+            Role::Author => &["post:write"],
+            Role::Moderator => &["comment:moderate"],
+        }
+    }
+}
+
+
+/// Fails a guard whose credential lookup returned `e`.
+fn lookup_failure<T>(e: Error) -> Outcome<T, Error> {
+    match e {
+        Error::RecordNotFound => Failure((Status::Unauthorized, e)),
+        Error::UnableToSendCommand(_) => Failure((Status::ServiceUnavailable, e)),
+        _ => Failure((Status::InternalServerError, e)),
+    }
+}
+
+
+/// Resolves the session in `request` to its user and hashed token.
+fn session_user(request: &Request) -> Outcome<(models::User, Role, String), Error> {
+    let token = match request_token(request) {
+        Some(ref token) if !token.starts_with(API_KEY_PREFIX) => hash_token(token),
+        _ => return Failure((Status::Unauthorized, Error::RecordNotFound)),
+    };
+    let found: DBResult<_> = DB_POOL.get()
+        .map_err(Error::from)
+        .and_then(|conn| user::find_session(&*conn, &token));
+    match found {
+        Ok(u) => match Role::parse(&u.role) {
+            Some(role) => Success((u, role, token)),
+            None => Failure((Status::Forbidden, Error::RecordNotFound)),
+        },
+        Err(e) => lookup_failure(e),
+    }
+}
+
+
+/// Request guard for routes that require any logged-in user.
+///
+/// API keys are never accepted here; use `Auth` for routes automation may call.
+pub struct User {
+    pub id: i32,
+    pub name: String,
+    pub role: Role,
+    session: String,
+}
+
+impl User {
+    /// Hash of the session token this request was authenticated with.
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for User {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        match session_user(request) {
+            Success((u, role, token)) => Success(User { id: u.id, name: u.name, role: role, session: token }),
+            Failure(f) => Failure(f),
+            Forward(f) => Forward(f),
+        }
+    }
+}
+
+
+/// Request guard for routes that require a logged-in admin.
+pub struct Admin(pub User);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        match User::from_request(request) {
+            Success(user) => if user.role == Role::Admin {
+                Success(Admin(user))
+            } else {
+                Failure((Status::Forbidden, Error::RecordNotFound))
+            },
+            Failure(f) => Failure(f),
+            Forward(f) => Forward(f),
+        }
+    }
+}
+
+
+/// A permission an API key or role can be granted.
+pub trait Scope {
+    fn name() -> &'static str;
+}
+
+macro_rules! scopes {
+    ($($scope:ident => $name:expr),*) => {
+        $(
+            pub struct $scope;
+
+            impl Scope for $scope {
+                fn name() -> &'static str { $name }
+            }
+        )*
+
+        /// Every scope name an API key may carry.
+        pub const SCOPES: &'static [&'static str] = &[$($name),*];
+    }
+}
+
+scopes! {
+    PostWrite => "post:write",
+    PostPublish => "post:publish",
+    CommentModerate => "comment:moderate",
+    VisitorRead => "visitor:read",
+    VisitorWrite => "visitor:write"
+}
+
+
+/// Who made an authorized request.
+pub enum Principal {
+    User(i32, Role),
+    ApiKey(i32),
+}
+
+
+/// Request guard admitting a user whose role, or an API key, holds scope `S`.
+pub struct Auth<S: Scope> {
+    pub principal: Principal,
+    _scope: PhantomData<S>,
+}
+
+impl<S: Scope> Auth<S> {
+    /// Id of the user behind this request, unless it was made with an API key.
+    pub fn user_id(&self) -> Option<i32> {
+        match self.principal {
+            Principal::User(id, _) => Some(id),
+            Principal::ApiKey(_) => None,
+        }
+    }
+
+    /// Whether the caller may modify `post`; authors only get their own drafts
+    /// and posts in review.
+    pub fn can_edit(&self, post: &Post) -> bool {
+        match self.principal {
+            Principal::User(id, Role::Author) => post.uid == Some(id)
+                && (post.state == State::Draft.as_str() || post.state == State::Review.as_str()),
+            _ => true,
+        }
+    }
+}
+
+impl<'a, 'r, S: Scope> FromRequest<'a, 'r> for Auth<S> {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let token = match request_token(request) {
+            Some(token) => token,
+            None => return Failure((Status::Unauthorized, Error::RecordNotFound)),
+        };
+        if !token.starts_with(API_KEY_PREFIX) {
+            return match session_user(request) {
+                Success((u, role, _)) => if role.scopes().contains(&S::name()) {
+                    Success(Auth { principal: Principal::User(u.id, role), _scope: PhantomData })
+                } else {
+                    Failure((Status::Forbidden, Error::RecordNotFound))
+                },
+                Failure(f) => Failure(f),
+                Forward(f) => Forward(f),
+            };
+        }
+
+        let found: DBResult<_> = DB_POOL.get()
+            .map_err(Error::from)
+            .and_then(|conn| api_key::authenticate(&*conn, &hash_token(&token)));
+        match found {
+            Ok(ref key) if key.scopes.iter().any(|s| s == S::name()) =>
+                Success(Auth { principal: Principal::ApiKey(key.id), _scope: PhantomData }),
+            Ok(_) => Failure((Status::Forbidden, Error::RecordNotFound)),
+            Err(e) => lookup_failure(e),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+// Environment
+use dotenv::dotenv;
+use std::env;
+
+use rocket;
+
+
+/// Looks up setting `name` in the environment (including `.env`), then as a
+/// lowercased extra of the active `Rocket.toml` environment.
+///
+/// `Rocket.toml` is only read once Rocket has been ignited.
+pub fn get(name: &str) -> Option<String> {
+    dotenv().ok();
+
+    env::var(name).ok().or_else(|| {
+        let key = name.to_lowercase();
+        rocket::config::active().and_then(|c| {
+            c.get_str(&key).map(String::from)
+                .or_else(|_| c.get_int(&key).map(|i| i.to_string()))
+                .ok()
+        })
+    })
+}
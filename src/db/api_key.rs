@@ -0,0 +1,97 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+// Timestamp
+use chrono::prelude::*;
+
+use schema::api_keys;
+use models::{ApiKey, NewApiKey};
+use db::{Error, DBResult};
+
+
+/// Creates an API key; `key` must already be hashed.
+pub fn create(conn: &PgConnection,
+              name: &str, key: &str, scopes: &[String], expires: Option<NaiveDateTime>) -> DBResult<ApiKey> {
+    let new_key = NewApiKey {
+        name: name.into(),
+        key: key.into(),
+        scopes: scopes.to_vec(),
+        expires: expires,
+    };
+
+    diesel::insert(&new_key).into(api_keys::table)
+        .get_result(conn)
+        .map(|key| key)
+        .map_err(Error::from)
+}
+
+
+pub fn get(conn: &PgConnection, id: Option<i32>) -> DBResult<Vec<ApiKey>> {
+    let mut query = api_keys::table.into_boxed();
+    if let Some(kid) = id {
+        query = query.filter(api_keys::id.eq(kid));
+    }
+
+    query.load::<ApiKey>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Looks up an unexpired key by its hash and records the use.
+pub fn authenticate(conn: &PgConnection, key: &str) -> DBResult<ApiKey> {
+    let now = UTC::now().naive_utc();
+    diesel::update(api_keys::table
+                   .filter(api_keys::key.eq(key))
+                   .filter(api_keys::expires.is_null().or(api_keys::expires.gt(now))))
+        .set(api_keys::last_used.eq(now))
+        .get_result(conn)
+        .map(|key| key)
+        .map_err(Error::from)
+}
+
+
+pub fn delete(conn: &PgConnection, id: i32) -> DBResult<usize> {
+    diesel::delete(api_keys::table.find(id))
+            .execute(conn)
+            .map_err(Error::from)
+            .and_then(|num| match num {
+                0 => Err(Error::RecordNotFound),
+                n => Ok(n)
+            })
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_api_key() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+
+        // Create
+        let scopes = vec!["post:write".to_string(), "post:publish".to_string()];
+        let key = create(conn, "ci", "hashed_key", &scopes, None).unwrap();
+        assert!(key.name == "ci" && key.scopes == scopes && key.last_used.is_none());
+
+        // Authenticate
+        let used = authenticate(conn, "hashed_key").unwrap();
+        assert!(used.id == key.id && used.last_used.is_some());
+        assert!(authenticate(conn, "wrong_key").err() == Some(Error::RecordNotFound));
+
+        let expired = UTC::now().naive_utc() - Duration::hours(1);
+        let old = create(conn, "old", "expired_key", &scopes, Some(expired)).unwrap();
+        assert!(authenticate(conn, "expired_key").err() == Some(Error::RecordNotFound));
+
+        // Delete
+        assert!(delete(conn, key.id).unwrap() == 1);
+        assert!(delete(conn, old.id).unwrap() == 1);
+        assert!(delete(conn, old.id).err() == Some(Error::RecordNotFound));
+        assert!(get(conn, Some(key.id)).unwrap().len() == 0);
+    }
+}
@@ -0,0 +1,56 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use schema::audit_log;
+use models::{AuditEntry, NewAuditEntry};
+use db::{Error, DBResult};
+
+
+/// Records that user `uid` performed `action` on `subject`, e.g. `visitor:12`.
+pub fn record(conn: &PgConnection, uid: Option<i32>, action: &str, subject: &str, detail: &str) -> DBResult<AuditEntry> {
+    let entry = NewAuditEntry {
+        uid: uid,
+        action: action.into(),
+        subject: subject.into(),
+        detail: detail.into(),
+    };
+
+    diesel::insert(&entry).into(audit_log::table)
+        .get_result(conn)
+        .map(|entry| entry)
+        .map_err(Error::from)
+}
+
+
+/// Everything recorded about `subject`, newest first.
+pub fn for_subject(conn: &PgConnection, subject: &str) -> DBResult<Vec<AuditEntry>> {
+    audit_log::table
+        .filter(audit_log::subject.eq(subject))
+        .order(audit_log::id.desc())
+        .load::<AuditEntry>(conn)
+        .map_err(Error::from)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_audit() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+
+        let subject = "test:audit";
+        let first = record(conn, None, "test.first", subject, "").unwrap();
+        let second = record(conn, None, "test.second", subject, "{\"n\":1}").unwrap();
+        let entries = for_subject(conn, subject).unwrap();
+        assert!(entries.len() == 2 && entries[0].id == second.id && entries[1].id == first.id);
+        assert!(entries[0].detail == "{\"n\":1}");
+
+        diesel::delete(audit_log::table.filter(audit_log::subject.eq(subject))).execute(conn).unwrap();
+    }
+}
@@ -2,91 +2,534 @@
 use diesel;
 use diesel::prelude::*;
 use diesel::data_types::PgTimestamp;
-use diesel::result::DatabaseErrorKind;
-use diesel::result::Error as DieselError;
-use diesel::pg::PgConnection;
+use diesel::pg::{Pg, PgConnection};
 
 // Timestamp
 use chrono::prelude::*;
 
-use schema::comments;
-use models::{Comment, NewComment};
-use db::{Error, DBResult};
+use std::collections::{HashMap, HashSet};
 
+use schema::{comments, posts};
+use models::{Comment, NewComment, Visitor};
+use db::{self, Error, DBResult, check_version, post, setting};
+use db::page::{Page, PageRequest};
+
+
+/// Moderation state of a comment; only approved comments are shown publicly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pending,
+    Approved,
+    Spam,
+    Rejected,
+    Deleted,
+}
+
+impl Status {
+    pub fn parse(s: &str) -> Option<Status> {
+        match s {
+            "pending" => Some(Status::Pending),
+            "approved" => Some(Status::Approved),
+            "spam" => Some(Status::Spam),
+            "rejected" => Some(Status::Rejected),
+            "deleted" => Some(Status::Deleted),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Status::Pending => "pending",
+            Status::Approved => "approved",
+            Status::Spam => "spam",
+            Status::Rejected => "rejected",
+            Status::Deleted => "deleted",
+        }
+    }
+}
+
+
+/// Which new comments wait in the queue before going live.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Always,
+    FirstTime,
+    Never,
+}
+
+impl Policy {
+    pub fn parse(s: &str) -> Option<Policy> {
+        match s {
+            "always" => Some(Policy::Always),
+            "first_time" => Some(Policy::FirstTime),
+            "never" => Some(Policy::Never),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Policy::Always => "always",
+            Policy::FirstTime => "first_time",
+            Policy::Never => "never",
+        }
+    }
+}
+
+const POLICY_SETTING: &'static str = "moderation";
+
+
+pub fn policy(conn: &PgConnection) -> DBResult<Policy> {
+    let value = setting::get(conn, POLICY_SETTING)?;
+    Ok(value.and_then(|v| Policy::parse(&v)).unwrap_or(Policy::FirstTime))
+}
+
+
+pub fn set_policy(conn: &PgConnection, policy: Policy) -> DBResult<Policy> {
+    setting::set(conn, POLICY_SETTING, policy.as_str())
+        .map(|_| policy)
+}
+
+
+/// Status a new comment by `visitor` starts in under the site policy.
+///
+/// With `Policy::FirstTime`, visitors skip the queue once they have verified
+/// their address or any of their comments has been approved.
+pub fn initial_status(conn: &PgConnection, visitor: &Visitor) -> DBResult<Status> {
+    match policy(conn)? {
+        Policy::Always => Ok(Status::Pending),
+        Policy::Never => Ok(Status::Approved),
+        Policy::FirstTime if visitor.verified_at.is_some() => Ok(Status::Approved),
+        Policy::FirstTime => {
+            let approved: i64 = comments::table
+                .filter(comments::vid.eq(visitor.id))
+                .filter(comments::status.eq(Status::Approved.as_str()))
+                .count()
+                .get_result(conn)?;
+            Ok(if approved > 0 { Status::Approved } else { Status::Pending })
+        }
+    }
+}
+
+
+/// Creates a comment; a reply's approved parent must be on the same post.
+pub fn create(conn: &PgConnection, pid: i32, vid: i32, body: &str, parent_id: Option<i32>,
+              status: Status, spam_score: Option<f64>) -> DBResult<Comment> {
+    if let Some(parent) = parent_id {
+        let parent = comments::table.find(parent)
+            .filter(comments::status.eq(Status::Approved.as_str()))
+            .first::<Comment>(conn)
+            .map_err(|e| match Error::from(e) {
+                Error::RecordNotFound => Error::ForeignKeyViolation,
+                e => e
+            })?;
+        if parent.pid != pid {
+            return Err(Error::ForeignKeyViolation);
+        }
+    }
 
-pub fn create(conn: &PgConnection, pid: i32, vid: i32, body: &str) -> DBResult<Comment> {
     let new_cmt = NewComment {
         pid: pid,
         vid: vid,
         body: body.into(),
+        parent_id: parent_id,
+        status: status.as_str().into(),
+        spam_score: spam_score,
     };
 
     diesel::insert(&new_cmt).into(comments::table)
         .get_result(conn)
         .map(|cmt| cmt)
-        .map_err(|e| match e {
-            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Error::ForeignKeyViolation,
-            _ => Error::DatabaseError
-        })
+        .map_err(Error::from)
 }
 
 
-pub fn update(conn: &PgConnection, id: i32, body: &str) -> DBResult<Comment> {
+/// The error for a comment that changed under a compare-and-swap update.
+fn changed(conn: &PgConnection, id: i32) -> Error {
+    match comments::table.find(id).first::<Comment>(conn) {
+        Ok(c) => Error::VersionMismatch(db::version(c.last_edited)),
+        Err(e) => Error::from(e),
+    }
+}
+
+
+/// Replaces the body of comment `id`; with `version`, only of that version.
+pub fn update(conn: &PgConnection, id: i32, body: &str, version: Option<i64>) -> DBResult<Comment> {
     let millennium= NaiveDateTime::from_timestamp(946684800, 0);
     let now = UTC::now().naive_utc();
     let ts = now.signed_duration_since(millennium).num_microseconds().unwrap();
-    diesel::update(comments::table.find(id))
-        .set((
-                comments::body.eq(body),
-                comments::last_edited.eq(PgTimestamp(ts))
-             ))
-        .get_result(conn)
-        .map(|post| post)
-        .map_err(|e| match e {
-            DieselError::NotFound => Error::RecordNotFound,
-            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Error::ForeignKeyViolation,
-            _ => Error::DatabaseError
-        })
+    conn.transaction(|| {
+        let current = comments::table.find(id).first::<Comment>(conn)?;
+        check_version(current.last_edited, version)?;
+        let updated = diesel::update(comments::table.find(id).filter(comments::last_edited.eq(current.last_edited)))
+            .set((
+                    comments::body.eq(body),
+                    comments::last_edited.eq(PgTimestamp(ts))
+                 ))
+            .get_result::<Comment>(conn)
+            .optional()?;
+        match updated {
+            Some(comment) => Ok(comment),
+            None => Err(changed(conn, id)),
+        }
+    })
 }
 
 
-pub fn get(conn: &PgConnection, id: Option<i32>, non_deleted_only: bool) -> Vec<Comment> {
+pub fn get(conn: &PgConnection, id: Option<i32>, approved_only: bool) -> DBResult<Vec<Comment>> {
     let mut query = comments::table.into_boxed();
     if let Some(cid) = id {
         query = query.filter(comments::id.eq(cid));
     }
-    if non_deleted_only {
-        query = query.filter(comments::deleted.eq(false));
+    if approved_only {
+        query = query.filter(comments::status.eq(Status::Approved.as_str()));
     }
 
-    let ret = query.load::<Comment>(conn);
-    match ret {
-        Ok(v) => v,
-        _ => Vec::new()
+    query.load::<Comment>(conn)
+        .map_err(Error::from)
+}
+
+
+pub fn get_many(conn: &PgConnection, ids: &[i32]) -> DBResult<Vec<Comment>> {
+    comments::table.filter(comments::id.eq_any(ids.to_vec()))
+        .load::<Comment>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Every comment by visitor `vid` whatever its status, oldest first.
+pub fn get_by_visitor(conn: &PgConnection, vid: i32) -> DBResult<Vec<Comment>> {
+    comments::table.filter(comments::vid.eq(vid))
+        .order((comments::created.asc(), comments::id.asc()))
+        .load::<Comment>(conn)
+        .map_err(Error::from)
+}
+
+
+/// A comment with its replies, oldest first.
+pub struct Thread {
+    pub comment: Comment,
+    pub replies: Vec<Thread>,
+}
+
+
+/// Loads every comment on a post as a reply tree at most `max_depth` deep.
+pub fn thread(conn: &PgConnection, pid: i32, max_depth: usize) -> DBResult<Vec<Thread>> {
+    let all = comments::table.filter(comments::pid.eq(pid))
+        .order((comments::created.asc(), comments::id.asc()))
+        .load::<Comment>(conn)?;
+    Ok(nest(all, max_depth))
+}
+
+
+/// Nests comments given in `(created, id)` order.
+///
+/// Replies below `max_depth` are flattened into their ancestor at that depth.
+/// Comments that are not approved are kept only while they still have
+/// approved replies.
+pub fn nest(comments: Vec<Comment>, max_depth: usize) -> Vec<Thread> {
+    let ids: HashSet<i32> = comments.iter().map(|c| c.id).collect();
+    let mut children: HashMap<Option<i32>, Vec<Comment>> = HashMap::new();
+    for c in comments {
+        let parent = match c.parent_id {
+            Some(p) if ids.contains(&p) => Some(p),
+            _ => None,
+        };
+        children.entry(parent).or_insert_with(Vec::new).push(c);
+    }
+    nest_level(None, 1, max_depth, &mut children)
+}
+
+fn nest_level(parent: Option<i32>, depth: usize, max_depth: usize,
+              children: &mut HashMap<Option<i32>, Vec<Comment>>) -> Vec<Thread> {
+    let level = children.remove(&parent).unwrap_or_default();
+    level.into_iter().filter_map(|c| {
+        let replies = if depth >= max_depth {
+            let mut flat = Vec::new();
+            descendants(c.id, children, &mut flat);
+            flat.sort_by_key(|r| (r.created, r.id));
+            flat.into_iter()
+                .filter(|r| r.status == Status::Approved.as_str())
+                .map(|r| Thread { comment: r, replies: Vec::new() })
+                .collect()
+        } else {
+            nest_level(Some(c.id), depth + 1, max_depth, children)
+        };
+
+        if c.status != Status::Approved.as_str() && replies.is_empty() {
+            None
+        } else {
+            Some(Thread { comment: c, replies: replies })
+        }
+    }).collect()
+}
+
+fn descendants(id: i32, children: &mut HashMap<Option<i32>, Vec<Comment>>, out: &mut Vec<Comment>) {
+    for c in children.remove(&Some(id)).unwrap_or_default() {
+        descendants(c.id, children, out);
+        out.push(c);
+    }
+}
+
+
+/// Filters for `list`.
+#[derive(Default)]
+pub struct Filter {
+    pub pid: Option<i32>,
+    pub vid: Option<i32>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    pub status: Option<Status>,
+    /// Only comments on posts that are live.
+    pub live_posts: bool,
+}
+
+fn filtered(filter: &Filter) -> comments::BoxedQuery<'static, Pg> {
+    let mut query = comments::table.into_boxed();
+    if filter.live_posts {
+        let live = post::Filter { published: Some(true), ..post::Filter::default() };
+        query = query.filter(comments::pid.eq_any(post::filtered(&live).select(posts::id)));
+    }
+    if let Some(pid) = filter.pid {
+        query = query.filter(comments::pid.eq(pid));
+    }
+    if let Some(vid) = filter.vid {
+        query = query.filter(comments::vid.eq(vid));
     }
+    if let Some(since) = filter.since {
+        query = query.filter(comments::created.ge(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(comments::created.lt(until));
+    }
+    if let Some(status) = filter.status {
+        query = query.filter(comments::status.eq(status.as_str()));
+    }
+    query
+}
+
+
+/// Loads one page of comments in `(created, id)` keyset order.
+pub fn list(conn: &PgConnection, filter: &Filter, page: &PageRequest) -> DBResult<Page<Comment>> {
+    keyset_page!(conn, comments, Comment, filtered(filter), page)
+}
+
+
+/// Moves the given comments to `status`; fails only if none of them exist.
+///
+/// Deleting remembers the status each comment had, for `restore`.
+pub fn set_status(conn: &PgConnection, ids: &[i32], status: Status) -> DBResult<usize> {
+    let selected = || comments::table.filter(comments::id.eq_any(ids.to_vec()));
+    let num = conn.transaction(|| {
+        if status != Status::Deleted {
+            return diesel::update(selected())
+                .set((comments::status.eq(status.as_str()),
+                      comments::deleted_at.eq(None::<NaiveDateTime>),
+                      comments::prior_status.eq(None::<String>)))
+                .execute(conn);
+        }
+        let now = UTC::now().naive_utc();
+        let mut num = diesel::update(selected().filter(comments::status.eq(Status::Deleted.as_str())))
+            .set(comments::status.eq(Status::Deleted.as_str()))
+            .execute(conn)?;
+        for prior in &[Status::Pending, Status::Approved, Status::Spam, Status::Rejected] {
+            num += diesel::update(selected().filter(comments::status.eq(prior.as_str())))
+                .set((comments::status.eq(Status::Deleted.as_str()),
+                      comments::deleted_at.eq(now),
+                      comments::prior_status.eq(prior.as_str())))
+                .execute(conn)?;
+        }
+        Ok(num)
+    });
+    num.map_err(Error::from)
+        .and_then(|num| match num {
+            0 => Err(Error::RecordNotFound),
+            n => Ok(n)
+        })
 }
 
 
-pub fn delete(conn: &PgConnection, id: i32) -> DBResult<usize> {
-    diesel::update(comments::table.find(id))
-            .set(comments::deleted.eq(true))
+/// Flags an approved comment as announced to subscribers; returns false if it
+/// already was, so that nobody hears about a comment twice.
+pub fn mark_notified(conn: &PgConnection, id: i32) -> DBResult<bool> {
+    diesel::update(comments::table.find(id)
+                       .filter(comments::status.eq(Status::Approved.as_str()))
+                       .filter(comments::notified.eq(false)))
+            .set(comments::notified.eq(true))
             .execute(conn)
-            .map(|num| num)
-            .map_err(|e| match e {
-                DieselError::NotFound => Error::RecordNotFound,
-                _ => Error::DatabaseError
+            .map(|num| num == 1)
+            .map_err(Error::from)
+}
+
+
+/// Moves comment `id` to the trash; with `version`, only that version of it.
+/// Fails with `VersionMismatch` if it changes meanwhile.
+pub fn delete(conn: &PgConnection, id: i32, version: Option<i64>) -> DBResult<usize> {
+    conn.transaction(|| {
+        let current = comments::table.find(id).first::<Comment>(conn)?;
+        check_version(current.last_edited, version)?;
+        if current.status == Status::Deleted.as_str() {
+            return Ok(1);
+        }
+        diesel::update(comments::table.find(id)
+                           .filter(comments::last_edited.eq(current.last_edited))
+                           .filter(comments::status.eq(current.status.as_str())))
+            .set((comments::status.eq(Status::Deleted.as_str()),
+                  comments::deleted_at.eq(UTC::now().naive_utc()),
+                  comments::prior_status.eq(current.status.as_str())))
+            .execute(conn)
+            .map_err(Error::from)
+            .and_then(|num| match num {
+                0 => Err(changed(conn, id)),
+                n => Ok(n)
             })
+    })
+}
+
+
+/// Deleted comments, most recently deleted first.
+pub fn trash(conn: &PgConnection) -> DBResult<Vec<Comment>> {
+    comments::table.filter(comments::status.eq(Status::Deleted.as_str()))
+        .order((comments::deleted_at.desc(), comments::id.desc()))
+        .load::<Comment>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Takes a comment out of the trash with the status it had before.
+pub fn restore(conn: &PgConnection, id: i32) -> DBResult<Comment> {
+    let deleted = comments::table.find(id)
+        .filter(comments::status.eq(Status::Deleted.as_str()))
+        .first::<Comment>(conn)?;
+    let status = deleted.prior_status.as_ref()
+        .and_then(|s| Status::parse(s))
+        .unwrap_or(Status::Pending);
+    set_status(conn, &[id], status)?;
+    comments::table.find(id)
+        .first::<Comment>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Removes every comment by visitor `vid`; returns how many there were.
+///
+/// Comments that others replied to are blanked and kept as deleted
+/// placeholders so that the replies stay in their threads.
+pub fn remove_by_visitor(conn: &PgConnection, vid: i32) -> DBResult<usize> {
+    let ids = comments::table.filter(comments::vid.eq(vid))
+        .select(comments::id)
+        .load::<i32>(conn)?;
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    diesel::update(comments::table.filter(comments::vid.eq(vid)))
+            .set((comments::body.eq(""), comments::spam_score.eq(None::<f64>)))
+            .execute(conn)?;
+    set_status(conn, &ids, Status::Deleted)?;
+    purge_leaves(conn, ids.clone())?;
+    Ok(ids.len())
 }
 
 
+/// Removes the given comments for good, keeping those that still have
+/// replies outside `ids` as placeholders; returns how many went.
+///
+/// Goes from the leaves up, since a reply removed in one pass may free its
+/// parent.
+fn purge_leaves(conn: &PgConnection, ids: Vec<i32>) -> DBResult<usize> {
+    let mut remaining = ids;
+    let mut num = 0;
+    loop {
+        let parents: HashSet<i32> = comments::table.filter(comments::parent_id.eq_any(remaining.clone()))
+            .select(comments::parent_id)
+            .load::<Option<i32>>(conn)?
+            .into_iter()
+            .filter_map(|p| p)
+            .collect();
+        let (leaves, rest): (Vec<i32>, Vec<i32>) = remaining.into_iter().partition(|id| !parents.contains(id));
+        if leaves.is_empty() {
+            return Ok(num);
+        }
+        num += diesel::delete(comments::table.filter(comments::id.eq_any(leaves))).execute(conn)?;
+        remaining = rest;
+    }
+}
+
+
+/// Removes the given comments for good, moving their replies outside `ids`
+/// up a level; returns how many went.
+///
+/// Replies outside `ids` move up to the nearest ancestor that stays, or to
+/// the top level, so purging a parent never takes its live replies along.
+fn purge_ids(conn: &PgConnection, ids: Vec<i32>) -> DBResult<usize> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    conn.transaction(|| {
+        let parents: HashMap<i32, Option<i32>> = comments::table.filter(comments::id.eq_any(ids.clone()))
+            .select((comments::id, comments::parent_id))
+            .load::<(i32, Option<i32>)>(conn)?
+            .into_iter()
+            .collect();
+        for (&id, &parent) in &parents {
+            let mut up = parent;
+            while let Some(&next) = up.and_then(|p| parents.get(&p)) {
+                up = next;
+            }
+            diesel::update(comments::table.filter(comments::parent_id.eq(id))
+                               .filter(comments::id.ne_any(ids.clone())))
+                    .set(comments::parent_id.eq(up))
+                    .execute(conn)?;
+        }
+        diesel::delete(comments::table.filter(comments::id.eq_any(ids.clone())))
+                .execute(conn)
+                .map_err(Error::from)
+    })
+}
+
+
+fn deleted_ids(conn: &PgConnection, before: Option<NaiveDateTime>) -> DBResult<Vec<i32>> {
+    let mut query = comments::table
+        .filter(comments::status.eq(Status::Deleted.as_str()))
+        .select(comments::id)
+        .into_boxed();
+    if let Some(before) = before {
+        query = query.filter(comments::deleted_at.lt(before));
+    }
+    query.load::<i32>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Empties the comment trash.
 pub fn purge(conn: &PgConnection) -> DBResult<usize> {
-    diesel::delete(comments::table.filter(comments::deleted.eq(true)))
+    let ids = deleted_ids(conn, None)?;
+    purge_ids(conn, ids)
+}
+
+
+/// Purges comments deleted before `before`.
+pub fn purge_before(conn: &PgConnection, before: NaiveDateTime) -> DBResult<usize> {
+    let ids = deleted_ids(conn, Some(before))?;
+    purge_ids(conn, ids)
+}
+
+
+/// Purges one deleted comment; its replies move up a level.
+pub fn purge_one(conn: &PgConnection, id: i32) -> DBResult<usize> {
+    comments::table.find(id)
+        .filter(comments::status.eq(Status::Deleted.as_str()))
+        .first::<Comment>(conn)?;
+    purge_ids(conn, vec![id])
+}
+
+
+/// Deletes all comments on posts `pids` for good, whatever their status.
+pub fn purge_for_posts(conn: &PgConnection, pids: &[i32]) -> DBResult<usize> {
+    diesel::delete(comments::table.filter(comments::pid.eq_any(pids.to_vec())))
             .execute(conn)
             .map(|num| num)
-            .map_err(|e| match e {
-                DieselError::NotFound => Error::RecordNotFound, // FIXME: necessary?
-                _ => Error::DatabaseError
-            })
+            .map_err(Error::from)
 }
 
 
@@ -107,25 +550,97 @@ mod test {
         let cats = vec!["tag1".into(), "tag2".into()];
         let body = "body1";
 
-        let post = post::create(conn, title, Some(&cats), body).unwrap();
-        let post = post::publish(conn, post.id).unwrap();
+        let post = post::create(conn, title, Some(&cats), body, None).unwrap();
+        let post = post::publish(conn, post.id, None).unwrap();
 
         let body = "comment body";
-        let visitor = visitor::create(conn, "visitor1", "test@test.com", None).unwrap();
-        let comment = create(conn, post.id, visitor.id, body).unwrap();
-        assert!(comment.body == body);
+        let visitor = visitor::create(conn, "visitor1", "comment@test.com", None).unwrap();
+        let comment = create(conn, post.id, visitor.id, body, None, Status::Pending, None).unwrap();
+        assert!(comment.body == body && comment.status == "pending");
         assert!(comment.vid == visitor.id, "vid: {}, visitor id: {}", comment.vid, visitor.id);
         assert!(comment.pid == post.id, "pid: {}, post id: {}", comment.pid, post.id);
 
+        // Update
+        let current = db::version(comment.last_edited);
+        assert!(update(conn, comment.id, "stale", Some(current + 1)).err() == Some(Error::VersionMismatch(current)));
+        assert!(delete(conn, comment.id, Some(current + 1)).err() == Some(Error::VersionMismatch(current)));
+        let comment = update(conn, comment.id, body, Some(current)).unwrap();
+        assert!(db::version(comment.last_edited) > current);
+
+        // Moderate
+        let site_policy = policy(conn).unwrap();
+        set_policy(conn, Policy::FirstTime).unwrap();
+        assert!(initial_status(conn, &visitor).unwrap() == Status::Pending);
+        assert!(get(conn, Some(comment.id), true).unwrap().is_empty());
+        let pending = Filter { pid: Some(post.id), status: Some(Status::Pending), ..Filter::default() };
+        assert!(list(conn, &pending, &PageRequest::default()).unwrap().total == 1);
+        let on_live = Filter { live_posts: true, ..pending };
+        assert!(list(conn, &on_live, &PageRequest::default()).unwrap().total == 0);
+        let unapproved = create(conn, post.id, visitor.id, "reply", Some(comment.id), Status::Approved, None);
+        assert!(unapproved.err() == Some(Error::ForeignKeyViolation));
+        let num = set_status(conn, &[comment.id, -1], Status::Approved).unwrap();
+        assert!(num == 1);
+        assert!(set_status(conn, &[-1], Status::Approved).err() == Some(Error::RecordNotFound));
+        assert!(initial_status(conn, &visitor).unwrap() == Status::Approved);
+        assert!(mark_notified(conn, comment.id).unwrap());
+        assert!(!mark_notified(conn, comment.id).unwrap());
+        set_policy(conn, site_policy).unwrap();
+
+        // Reply
+        let reply = create(conn, post.id, visitor.id, "reply", Some(comment.id), Status::Approved, None).unwrap();
+        assert!(reply.parent_id == Some(comment.id));
+        let other = post::create(conn, "other", None, "other", None).unwrap();
+        let wrong_post = create(conn, other.id, visitor.id, "reply", Some(comment.id), Status::Approved, None);
+        assert!(wrong_post.err() == Some(Error::ForeignKeyViolation));
+
         // Delete
-        let num = delete(conn, comment.id).unwrap();
+        let num = delete(conn, comment.id, None).unwrap();
         assert!(num == 1);
-        let num = purge(conn).unwrap();
-        println!("purged: {}", num);
+        let threads = thread(conn, post.id, 5).unwrap();
+        assert!(threads.len() == 1 && threads[0].comment.status == "deleted" && threads[0].replies.len() == 1);
+
+        // Trash
+        assert!(trash(conn).unwrap().iter().any(|c| c.id == comment.id && c.deleted_at.is_some()));
+        let restored = restore(conn, comment.id).unwrap();
+        assert!(restored.status == "approved" && restored.deleted_at.is_none());
+        assert!(restore(conn, comment.id).err() == Some(Error::RecordNotFound));
+        delete(conn, comment.id, None).unwrap();
+
+        // Purging a parent keeps its live reply, moved to the top level
+        assert!(purge_one(conn, comment.id).unwrap() == 1);
+        let moved = get(conn, Some(reply.id), true).unwrap().pop().unwrap();
+        assert!(moved.parent_id.is_none() && moved.status == "approved");
+
+        let num = delete(conn, reply.id, None).unwrap();
+        assert!(num == 1);
+        assert!(thread(conn, post.id, 5).unwrap().len() == 0);
+        let num = purge_one(conn, reply.id).unwrap();
         assert!(num == 1);
 
-        visitor::delete(conn, visitor.id).unwrap();
-        post::delete(conn, post.id).unwrap();
+        visitor::delete(conn, visitor.id, visitor::Cascade::Refuse).unwrap();
+        visitor::purge(conn).unwrap();
+        post::delete(conn, post.id, None).unwrap();
+        post::delete(conn, other.id, None).unwrap();
         post::purge(conn).unwrap();
     }
+
+    #[test]
+    fn test_nest() {
+        let ts = NaiveDate::from_ymd(2017, 5, 1).and_hms(0, 0, 0);
+        let cmt = |id: i32, parent: Option<i32>, status: Status| Comment {
+            id: id, pid: 1, vid: 1, body: String::new(), created: ts, last_edited: ts,
+            parent_id: parent, status: status.as_str().into(), spam_score: None, trained: None, notified: false,
+            deleted_at: None, prior_status: None,
+        };
+        let all = vec![cmt(1, None, Status::Deleted), cmt(2, Some(1), Status::Approved),
+                       cmt(3, Some(2), Status::Approved), cmt(4, Some(3), Status::Approved),
+                       cmt(5, None, Status::Deleted), cmt(6, Some(5), Status::Pending)];
+
+        let threads = nest(all, 2);
+        assert!(threads.len() == 1 && threads[0].comment.id == 1);
+        let reply = &threads[0].replies[0];
+        assert!(reply.comment.id == 2);
+        let flat: Vec<i32> = reply.replies.iter().map(|t| t.comment.id).collect();
+        assert!(flat == vec![3, 4]);
+    }
 }
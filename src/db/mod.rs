@@ -1,5 +1,7 @@
 // DB ORM
 use diesel::pg::PgConnection;
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error as DieselError;
 
 // Connection pool
 use r2d2::{ Pool, Config, PooledConnection, GetTimeout };
@@ -15,25 +17,103 @@ use rocket::Outcome::{Success, Failure};
 use rocket::http::Status;
 use rocket::Request;
 
+// Timestamp
+use chrono::NaiveDateTime;
 
+use std::fmt;
+
+
+#[macro_use]
+pub mod page;
 pub mod post;
 pub mod visitor;
 pub mod comment;
-
-
+pub mod user;
+pub mod api_key;
+pub mod setting;
+pub mod spam;
+pub mod rate_limit;
+pub mod outbox;
+pub mod subscription;
+pub mod audit;
+pub mod task;
+pub mod revision;
+pub mod tag;
+
+
+/// Database failure; the variants carrying a `String` keep the underlying
+/// cause so it can be logged without being shown to clients.
 #[derive(Debug, PartialEq, Eq)]
 pub enum Error {
     RecordNotFound,
     ForeignKeyViolation,
     UniqueViolation,
-    UnableToSendCommand,
-    DatabaseError,
+    /// A record cannot move from the first state to the second.
+    InvalidTransition(&'static str, &'static str),
+    /// A change needs a value that was not given; carries its name.
+    MissingParameter(&'static str),
+    /// The record changed since the version the caller saw; carries the
+    /// current one.
+    VersionMismatch(i64),
+    UnableToSendCommand(String),
+    DatabaseError(String),
+}
+
+impl From<DieselError> for Error {
+    fn from(e: DieselError) -> Error {
+        match e {
+            DieselError::NotFound => Error::RecordNotFound,
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Error::ForeignKeyViolation,
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Error::UniqueViolation,
+            DieselError::DatabaseError(DatabaseErrorKind::UnableToSendCommand, ref info) =>
+                Error::UnableToSendCommand(info.message().into()),
+            _ => Error::DatabaseError(e.to_string())
+        }
+    }
+}
+
+impl From<GetTimeout> for Error {
+    fn from(e: GetTimeout) -> Error {
+        Error::UnableToSendCommand(e.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::RecordNotFound => write!(f, "record not found"),
+            Error::ForeignKeyViolation => write!(f, "foreign key violation"),
+            Error::UniqueViolation => write!(f, "unique violation"),
+            Error::InvalidTransition(from, to) => write!(f, "cannot move from {} to {}", from, to),
+            Error::MissingParameter(name) => write!(f, "missing {}", name),
+            Error::VersionMismatch(current) => write!(f, "version mismatch, current version is {}", current),
+            Error::UnableToSendCommand(ref cause) => write!(f, "unable to reach database: {}", cause),
+            Error::DatabaseError(ref cause) => write!(f, "database error: {}", cause),
+        }
+    }
 }
 
 
 pub type DBResult<T> = Result<T, Error>;
 
 
+/// Version of an editable row for optimistic concurrency: its `last_edited`
+/// in microseconds since the Unix epoch.
+pub fn version(last_edited: NaiveDateTime) -> i64 {
+    last_edited.signed_duration_since(NaiveDateTime::from_timestamp(0, 0)).num_microseconds().unwrap()
+}
+
+
+/// Fails with `VersionMismatch` unless `expected` is absent or is the version
+/// of a row last edited at `last_edited`.
+pub fn check_version(last_edited: NaiveDateTime, expected: Option<i64>) -> DBResult<()> {
+    match expected {
+        Some(v) if v != version(last_edited) => Err(Error::VersionMismatch(version(last_edited))),
+        _ => Ok(()),
+    }
+}
+
+
 pub struct DB(PooledConnection<ConnectionManager<PgConnection>>);
 
 impl DB {
@@ -48,7 +128,7 @@ impl<'a, 'r> FromRequest<'a, 'r> for DB {
     fn from_request(_: &'a Request<'r>) -> Outcome<Self, Self::Error> {
         match DB_POOL.get() {
             Ok(conn) => Success(DB(conn)),
-            Err(e) => Failure((Status::InternalServerError, e)),
+            Err(e) => Failure((Status::ServiceUnavailable, e)),
         }
     }
 }
@@ -0,0 +1,116 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+// Timestamp
+use chrono::prelude::*;
+
+use schema::outbox;
+use models::{OutboxMail, NewOutboxMail};
+use db::{Error, DBResult};
+
+
+/// Mail is given up on after this many failed attempts.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+
+pub fn create(conn: &PgConnection, recipient: &str, subject: &str, body: &str) -> DBResult<OutboxMail> {
+    let new_mail = NewOutboxMail {
+        recipient: recipient.into(),
+        subject: subject.into(),
+        body: body.into(),
+    };
+
+    diesel::insert(&new_mail).into(outbox::table)
+        .get_result(conn)
+        .map(|mail| mail)
+        .map_err(Error::from)
+}
+
+
+/// Unsent mail whose next attempt is due by `now`, oldest first.
+pub fn due(conn: &PgConnection, now: NaiveDateTime, limit: i64) -> DBResult<Vec<OutboxMail>> {
+    outbox::table
+        .filter(outbox::sent_at.is_null())
+        .filter(outbox::attempts.lt(MAX_ATTEMPTS))
+        .filter(outbox::next_attempt.le(now))
+        .order(outbox::next_attempt.asc())
+        .limit(limit)
+        .load::<OutboxMail>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Reserves `mail` for delivery until `until`, unless another worker got it
+/// first; returns whether it did.
+pub fn claim(conn: &PgConnection, mail: &OutboxMail, until: NaiveDateTime) -> DBResult<bool> {
+    diesel::update(outbox::table.find(mail.id)
+                       .filter(outbox::sent_at.is_null())
+                       .filter(outbox::next_attempt.eq(mail.next_attempt)))
+        .set(outbox::next_attempt.eq(until))
+        .execute(conn)
+        .map(|num| num == 1)
+        .map_err(Error::from)
+}
+
+
+pub fn mark_sent(conn: &PgConnection, id: i32) -> DBResult<usize> {
+    let now = UTC::now().naive_utc();
+    diesel::update(outbox::table.find(id))
+        .set((outbox::sent_at.eq(now), outbox::attempts.eq(outbox::attempts + 1)))
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+
+/// Records a failed attempt and when to try again.
+pub fn mark_failed(conn: &PgConnection, id: i32, error: &str, retry_at: NaiveDateTime) -> DBResult<usize> {
+    diesel::update(outbox::table.find(id))
+        .set((outbox::attempts.eq(outbox::attempts + 1),
+              outbox::last_error.eq(error),
+              outbox::next_attempt.eq(retry_at)))
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+
+/// Deletes all mail to `recipient`, sent or not.
+pub fn delete_for_recipient(conn: &PgConnection, recipient: &str) -> DBResult<usize> {
+    diesel::delete(outbox::table.filter(outbox::recipient.eq(recipient)))
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_outbox() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+
+        let mail = create(conn, "outbox@test.com", "subject", "body").unwrap();
+        assert!(mail.attempts == 0 && mail.sent_at.is_none());
+        let later = UTC::now().naive_utc() + Duration::minutes(1);
+        assert!(due(conn, later, 1000).unwrap().iter().any(|m| m.id == mail.id));
+
+        // Claim
+        assert!(claim(conn, &mail, later).unwrap());
+        assert!(!claim(conn, &mail, later).unwrap());
+
+        // Retry
+        mark_failed(conn, mail.id, "refused", later).unwrap();
+        let retried = due(conn, later, 1000).unwrap().into_iter().find(|m| m.id == mail.id).unwrap();
+        assert!(retried.attempts == 1 && retried.last_error == Some("refused".into()));
+
+        mark_sent(conn, mail.id).unwrap();
+        assert!(!due(conn, later, 1000).unwrap().iter().any(|m| m.id == mail.id));
+
+        assert!(delete_for_recipient(conn, "outbox@test.com").unwrap() >= 1);
+    }
+}
@@ -0,0 +1,215 @@
+// Timestamp
+use chrono::prelude::*;
+
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+
+/// Keyset position of a row, ordered by `(created, id)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub created: NaiveDateTime,
+    pub id: i32,
+}
+
+impl Cursor {
+    pub fn new(created: NaiveDateTime, id: i32) -> Cursor {
+        Cursor { created: created, id: id }
+    }
+
+    /// Parses the `<microseconds>_<id>` form produced by `encode`; the error
+    /// says which part is wrong.
+    pub fn parse(s: &str) -> Result<Cursor, &'static str> {
+        let mut parts = s.splitn(2, '_');
+        let micros = parts.next().and_then(|m| m.parse::<i64>().ok())
+            .ok_or("expected <microseconds>_<id>")?;
+        let id = parts.next().ok_or("expected <microseconds>_<id>")?
+            .parse::<i32>().map_err(|_| "id is not a number")?;
+        // Floor division, so times before 1970 keep a positive fraction.
+        let (mut secs, mut frac) = (micros / 1_000_000, micros % 1_000_000);
+        if frac < 0 {
+            secs -= 1;
+            frac += 1_000_000;
+        }
+        NaiveDateTime::from_timestamp_opt(secs, frac as u32 * 1000)
+            .map(|dt| Cursor::new(dt, id))
+            .ok_or("time out of range")
+    }
+
+    pub fn encode(&self) -> String {
+        let micros = self.created.timestamp() * 1_000_000 + self.created.timestamp_subsec_micros() as i64;
+        format!("{}_{}", micros, self.id)
+    }
+}
+
+
+/// Loads one page of `$model` rows from the boxed query `$query` on
+/// `$table`, in `(created, id)` keyset order, as a `DBResult<Page<$model>>`.
+///
+/// `$query` is evaluated twice, once for the total count; a macro because the
+/// column types differ per table.
+macro_rules! keyset_page {
+    ($conn:expr, $table:ident, $model:ty, $query:expr, $page:expr) => {{
+        let page: &::db::page::PageRequest = $page;
+        let total: i64 = $query.count().get_result($conn)?;
+
+        let mut query = $query;
+        if let Some(c) = page.cursor() {
+            query = if page.descending() {
+                query.filter($table::created.lt(c.created)
+                             .or($table::created.eq(c.created).and($table::id.lt(c.id))))
+            } else {
+                query.filter($table::created.gt(c.created)
+                             .or($table::created.eq(c.created).and($table::id.gt(c.id))))
+            };
+        }
+        query = if page.descending() {
+            query.order(($table::created.desc(), $table::id.desc()))
+        } else {
+            query.order(($table::created.asc(), $table::id.asc()))
+        };
+
+        let rows = query.limit(page.limit + 1).load::<$model>($conn)?;
+        Ok(::db::page::Page::new(rows, total, page, |r| ::db::page::Cursor::new(r.created, r.id)))
+    }}
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    Newest,
+    Oldest,
+}
+
+impl Sort {
+    pub fn parse(s: &str) -> Option<Sort> {
+        match s {
+            "newest" => Some(Sort::Newest),
+            "oldest" => Some(Sort::Oldest),
+            _ => None
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    First,
+    After(Cursor),
+    Before(Cursor),
+}
+
+
+/// Which slice of a listing to load.
+#[derive(Debug, Clone, Copy)]
+pub struct PageRequest {
+    pub sort: Sort,
+    pub position: Position,
+    pub limit: i64,
+}
+
+impl PageRequest {
+    pub fn new(sort: Sort, position: Position, limit: i64) -> PageRequest {
+        PageRequest {
+            sort: sort,
+            position: position,
+            limit: if limit < 1 { 1 } else if limit > MAX_LIMIT { MAX_LIMIT } else { limit },
+        }
+    }
+
+    /// Whether the query must scan `(created, id)` in descending order.
+    ///
+    /// Loading the page before a cursor scans away from it, i.e. against `sort`.
+    pub fn descending(&self) -> bool {
+        match self.position {
+            Position::Before(_) => self.sort == Sort::Oldest,
+            _ => self.sort == Sort::Newest,
+        }
+    }
+
+    pub fn cursor(&self) -> Option<Cursor> {
+        match self.position {
+            Position::First => None,
+            Position::After(c) | Position::Before(c) => Some(c),
+        }
+    }
+}
+
+impl Default for PageRequest {
+    fn default() -> PageRequest {
+        PageRequest::new(Sort::Newest, Position::First, DEFAULT_LIMIT)
+    }
+}
+
+
+#[derive(Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from up to `limit + 1` rows loaded in scan order.
+    pub fn new<F>(mut rows: Vec<T>, total: i64, req: &PageRequest, key: F) -> Page<T>
+        where F: Fn(&T) -> Cursor
+    {
+        let more = rows.len() as i64 > req.limit;
+        rows.truncate(req.limit as usize);
+        if let Position::Before(_) = req.position {
+            rows.reverse();
+        }
+
+        let first = rows.first().map(|r| key(r).encode());
+        let last = rows.last().map(|r| key(r).encode());
+        let (next, prev) = match req.position {
+            Position::First => (if more { last } else { None }, None),
+            Position::After(_) => (if more { last } else { None }, first),
+            Position::Before(_) => (last, if more { first } else { None }),
+        };
+        Page { items: rows, total: total, next: next, prev: prev }
+    }
+
+    pub fn map<U, F: FnMut(T) -> U>(self, f: F) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            next: self.next,
+            prev: self.prev,
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cursor() {
+        let dt = NaiveDate::from_ymd(2017, 4, 1).and_hms_micro(12, 30, 5, 123456);
+        let cursor = Cursor::new(dt, 42);
+        assert!(Cursor::parse(&cursor.encode()) == Ok(cursor));
+        let old = Cursor::new(NaiveDate::from_ymd(1969, 7, 20).and_hms_micro(20, 17, 40, 250000), 7);
+        assert!(Cursor::parse(&old.encode()) == Ok(old));
+        assert!(Cursor::parse("garbage").is_err());
+        assert!(Cursor::parse("12_x") == Err("id is not a number"));
+    }
+
+    #[test]
+    fn test_page() {
+        let key = |n: &i32| Cursor::new(NaiveDate::from_ymd(2017, 1, 1).and_hms(0, 0, 0), *n);
+
+        let first = PageRequest::new(Sort::Newest, Position::First, 2);
+        let page = Page::new(vec![5, 4, 3], 5, &first, &key);
+        assert!(page.items == vec![5, 4] && page.next.is_some() && page.prev.is_none());
+
+        let before = PageRequest::new(Sort::Newest, Position::Before(key(&3)), 2);
+        assert!(!before.descending());
+        let page = Page::new(vec![4, 5], 5, &before, &key);
+        assert!(page.items == vec![5, 4] && page.next.is_some() && page.prev.is_none());
+    }
+}
@@ -2,63 +2,184 @@
 use diesel;
 use diesel::prelude::*;
 use diesel::data_types::PgTimestamp;
-use diesel::result::Error as DieselError;
-use diesel::pg::PgConnection;
+use diesel::expression::dsl::sql;
+use diesel::types::Integer;
+use diesel::pg::{Pg, PgConnection};
 
 // Timestamp
 use chrono::prelude::*;
 
-use models::{Post, NewPost};
-use db::{Error, DBResult};
+use std::cmp;
 
+use models::{Post, NewPost, PostTransition, NewPostTransition, PostRevision};
+use db::{Error, DBResult, check_version, comment, revision, tag, version};
+use db::page::{Page, PageRequest};
 
-fn serialize_categories(cats: Option<&Vec<String>>) -> String {
-    cats.map_or("".into(), |v| v.join(","))
+
+/// Editorial state of a post. Published posts are live, and so are scheduled
+/// ones once their `publish_at` has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Draft,
+    Review,
+    Scheduled,
+    Published,
+    Unpublished,
+    Archived,
+}
+
+impl State {
+    pub fn parse(s: &str) -> Option<State> {
+        match s {
+            "draft" => Some(State::Draft),
+            "review" => Some(State::Review),
+            "scheduled" => Some(State::Scheduled),
+            "published" => Some(State::Published),
+            "unpublished" => Some(State::Unpublished),
+            "archived" => Some(State::Archived),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            State::Draft => "draft",
+            State::Review => "review",
+            State::Scheduled => "scheduled",
+            State::Published => "published",
+            State::Unpublished => "unpublished",
+            State::Archived => "archived",
+        }
+    }
+
+    /// Whether a post may move from this state to `to`; scheduled posts may be
+    /// rescheduled.
+    pub fn can_move_to(&self, to: State) -> bool {
+        use self::State::*;
+
+        match (*self, to) {
+            (Draft, Review) | (Draft, Scheduled) | (Draft, Published) | (Draft, Archived) |
+            (Review, Draft) | (Review, Scheduled) | (Review, Published) |
+            (Scheduled, Draft) | (Scheduled, Scheduled) | (Scheduled, Published) |
+            (Published, Unpublished) | (Published, Archived) |
+            (Unpublished, Draft) | (Unpublished, Scheduled) | (Unpublished, Published) | (Unpublished, Archived) |
+            (Archived, Draft) => true,
+            _ => false,
+        }
+    }
 }
 
 
+/// Whether `post` is live at `now`: published, or scheduled for no later.
+pub fn is_live(post: &Post, now: NaiveDateTime) -> bool {
+    post.state == State::Published.as_str()
+        || (post.state == State::Scheduled.as_str() && post.publish_at.map_or(false, |t| t <= now))
+}
+
+
+/// Creates a post with its tags and its first revision.
 pub fn create(conn: &PgConnection,
-                       title: &str, categories: Option<&Vec<String>>, body: &str) -> DBResult<Post> {
+                       title: &str, categories: Option<&Vec<String>>, body: &str, uid: Option<i32>) -> DBResult<Post> {
     use schema::posts;
 
     let new_post = NewPost {
         title: title.into(),
-        category: serialize_categories(categories),
         body: body.into(),
+        uid: uid,
     };
 
-    diesel::insert(&new_post).into(posts::table)
-        .get_result(conn)
-        .map(|post| post)
-        .map_err(|_| Error::DatabaseError)
+    conn.transaction(|| {
+        let post = diesel::insert(&new_post).into(posts::table)
+            .get_result::<Post>(conn)?;
+        tag::set_for_post(conn, post.id, categories.map_or(&[][..], |v| &v[..]))?;
+        let names = tag::for_post(conn, post.id)?;
+        revision::record(conn, &post, &names, uid)?;
+        Ok(post)
+    })
+}
+
+
+/// `last_edited` of an edit made now.
+fn edit_stamp() -> PgTimestamp {
+    let millennium = NaiveDateTime::from_timestamp(946684800, 0);
+    let now = UTC::now().naive_utc();
+    PgTimestamp(now.signed_duration_since(millennium).num_microseconds().unwrap())
 }
 
 
-pub fn update(conn: &PgConnection,
-                       id: i32, title: &str, categories: Option<&Vec<String>>, body: &str) -> DBResult<Post> {
+/// Overwrites the content of post `id` and records it as a new revision by
+/// user `uid`. With `version` the write only applies to that version of the
+/// post; without it, to the version read here.
+fn write(conn: &PgConnection, id: i32, title: &str, categories: &[String], body: &str,
+         uid: Option<i32>, version: Option<i64>) -> DBResult<(Post, PostRevision)> {
     use schema::posts;
 
-    let cat = serialize_categories(categories);
-    let millennium= NaiveDateTime::from_timestamp(946684800, 0);
-    let now = UTC::now().naive_utc();
-    let ts = now.signed_duration_since(millennium).num_microseconds().unwrap();
-    diesel::update(posts::table.find(id))
-        .set((
-                posts::title.eq(title),
-                posts::category.eq(cat),
-                posts::body.eq(body),
-                posts::last_edited.eq(PgTimestamp(ts))
-             ))
-        .get_result(conn)
-        .map(|post| post)
-        .map_err(|e| match e {
-            DieselError::NotFound => Error::RecordNotFound,
-            _ => Error::DatabaseError
-        })
-}
-
-
-pub fn get(conn: &PgConnection, id: Option<i32>, published_only: bool, non_deleted_only: bool) -> Vec<Post> {
+    conn.transaction(|| {
+        let current = posts::table.find(id).first::<Post>(conn)?;
+        check_version(current.last_edited, version)?;
+        let post = diesel::update(posts::table.find(id).filter(posts::last_edited.eq(current.last_edited)))
+            .set((
+                    posts::title.eq(title),
+                    posts::body.eq(body),
+                    posts::last_edited.eq(edit_stamp())
+                 ))
+            .get_result::<Post>(conn)
+            .optional()?;
+        let post = match post {
+            Some(post) => post,
+            None => return Err(changed(conn, id)),
+        };
+        tag::set_for_post(conn, id, categories)?;
+        let names = tag::for_post(conn, id)?;
+        let revision = revision::record(conn, &post, &names, uid)?;
+        Ok((post, revision))
+    })
+}
+
+
+/// Records a new revision of each of posts `ids` by user `uid` after their
+/// tags were renamed or merged, which also changes their version.
+pub fn retagged(conn: &PgConnection, ids: &[i32], uid: Option<i32>) -> DBResult<()> {
+    use schema::posts;
+
+    let posts = diesel::update(posts::table.filter(posts::id.eq_any(ids.to_vec())))
+        .set(posts::last_edited.eq(edit_stamp()))
+        .get_results::<Post>(conn)?;
+    let mut tagged = tag::for_posts(conn, ids)?;
+    for post in posts {
+        let names = tagged.remove(&post.id).unwrap_or(vec![]);
+        revision::record(conn, &post, &names, uid)?;
+    }
+    Ok(())
+}
+
+
+/// The error for a post that changed under a compare-and-swap update.
+fn changed(conn: &PgConnection, id: i32) -> Error {
+    use schema::posts;
+
+    match posts::table.find(id).first::<Post>(conn) {
+        Ok(post) => Error::VersionMismatch(version(post.last_edited)),
+        Err(e) => Error::from(e),
+    }
+}
+
+
+pub fn update(conn: &PgConnection, id: i32, title: &str, categories: Option<&Vec<String>>, body: &str,
+              uid: Option<i32>, version: Option<i64>) -> DBResult<Post> {
+    write(conn, id, title, categories.map_or(&[][..], |v| &v[..]), body, uid, version).map(|(post, _)| post)
+}
+
+
+/// Brings back the content of revision `rev` of post `id` as a new revision,
+/// which is returned.
+pub fn restore_revision(conn: &PgConnection, id: i32, rev: i32, uid: Option<i32>) -> DBResult<PostRevision> {
+    let old = revision::get(conn, id, rev)?;
+    write(conn, id, &old.title, &old.categories, &old.body, uid, None).map(|(_, revision)| revision)
+}
+
+
+pub fn get(conn: &PgConnection, id: Option<i32>, published_only: bool, non_deleted_only: bool) -> DBResult<Vec<Post>> {
     use schema::posts;
 
     let mut query = posts::table.into_boxed();
@@ -66,73 +187,328 @@ pub fn get(conn: &PgConnection, id: Option<i32>, published_only: bool, non_delet
         query = query.filter(posts::id.eq(pid));
     }
     if published_only {
-        query = query.filter(posts::published.eq(true));
+        let now = UTC::now().naive_utc();
+        query = query.filter(posts::state.eq(State::Published.as_str())
+                             .or(posts::state.eq(State::Scheduled.as_str()).and(posts::publish_at.le(now))));
     }
     if non_deleted_only {
         query = query.filter(posts::deleted.eq(false));
     }
 
-    let ret = query.load::<Post>(conn);
-    match ret {
-        Ok(v) => v,
-        _ => Vec::new()
-    }
+    query.load::<Post>(conn)
+        .map_err(Error::from)
 }
 
-pub fn get_published(conn: &PgConnection, id: Option<i32>) -> Vec<Post> {
+pub fn get_published(conn: &PgConnection, id: Option<i32>) -> DBResult<Vec<Post>> {
     get(conn, id, true, true)
 }
 
 
-pub fn get_all(conn: &PgConnection) -> Vec<Post> {
+pub fn get_all(conn: &PgConnection) -> DBResult<Vec<Post>> {
     get(conn, None, false, false)
 }
 
 
-pub fn publish(conn: &PgConnection, id: i32) -> DBResult<Post> {
+/// Filters for `list`; deleted posts are never listed.
+#[derive(Default)]
+pub struct Filter {
+    /// Tag slug.
+    pub tag: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    /// Live posts, or every post that is not live.
+    pub published: Option<bool>,
+    pub state: Option<State>,
+    /// Limits posts that are not live to those written by this user.
+    pub author: Option<i32>,
+}
+
+pub fn filtered(filter: &Filter) -> ::schema::posts::BoxedQuery<'static, Pg> {
+    use schema::posts;
+
+    let mut query = posts::table.into_boxed();
+    query = query.filter(posts::deleted.eq(false));
+    if let Some(ref slug) = filter.tag {
+        use schema::{post_tags, tags};
+        let tids = tags::table.filter(tags::slug.eq(slug.clone())).select(tags::id);
+        query = query.filter(posts::id.eq_any(post_tags::table.filter(post_tags::tid.eq_any(tids))
+                                              .select(post_tags::pid)));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(posts::created.ge(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(posts::created.lt(until));
+    }
+    if let Some(published) = filter.published {
+        let now = UTC::now().naive_utc();
+        query = if published {
+            query.filter(posts::state.eq(State::Published.as_str())
+                         .or(posts::state.eq(State::Scheduled.as_str()).and(posts::publish_at.le(now))))
+        } else {
+            query.filter(posts::state.ne(State::Published.as_str())
+                         .and(posts::state.ne(State::Scheduled.as_str()).or(posts::publish_at.gt(now))))
+        };
+    }
+    if let Some(state) = filter.state {
+        query = query.filter(posts::state.eq(state.as_str()));
+    }
+    if let Some(uid) = filter.author {
+        let now = UTC::now().naive_utc();
+        query = query.filter(posts::uid.eq(uid)
+                             .or(posts::state.eq(State::Published.as_str()))
+                             .or(posts::state.eq(State::Scheduled.as_str()).and(posts::publish_at.le(now))));
+    }
+    query
+}
+
+
+/// Loads one page of posts in `(created, id)` keyset order.
+pub fn list(conn: &PgConnection, filter: &Filter, page: &PageRequest) -> DBResult<Page<Post>> {
+    use schema::posts;
+
+    keyset_page!(conn, posts, Post, filtered(filter), page)
+}
+
+
+/// Moves post `id` from its current state to `to` and records the transition
+/// by user `uid`. Scheduling keeps the post's `publish_at` unless `at` is given.
+fn move_to(conn: &PgConnection, id: i32, to: State, at: Option<NaiveDateTime>,
+           uid: Option<i32>, now: NaiveDateTime) -> DBResult<Post> {
+    use schema::{posts, post_transitions};
+
+    conn.transaction(|| {
+        let post = posts::table.find(id).filter(posts::deleted.eq(false)).first::<Post>(conn)?;
+        let from = State::parse(&post.state).expect("Invalid post state");
+        // A scheduled post past its time is live before `mark_live` sees it.
+        let live = from == State::Scheduled && is_live(&post, now);
+        let (publish_at, published_at) = match to {
+            State::Draft | State::Review => (None, None),
+            State::Scheduled => (at.or(post.publish_at), None),
+            State::Published if from == State::Scheduled => (post.publish_at.map(|t| cmp::min(t, now)), Some(now)),
+            State::Published => (Some(now), Some(now)),
+            State::Unpublished | State::Archived if live => (post.publish_at, post.publish_at),
+            State::Unpublished | State::Archived => (post.publish_at, post.published_at),
+        };
+        if !from.can_move_to(to) && !(live && State::Published.can_move_to(to)) {
+            return Err(Error::InvalidTransition(from.as_str(), to.as_str()));
+        }
+        if to == State::Scheduled && publish_at.is_none() {
+            return Err(Error::MissingParameter("publish_at"));
+        }
+
+        let post = diesel::update(posts::table.find(id).filter(posts::state.eq(from.as_str())))
+            .set((posts::state.eq(to.as_str()),
+                  posts::publish_at.eq(publish_at),
+                  posts::published_at.eq(published_at)))
+            .get_result::<Post>(conn)?;
+        let record = NewPostTransition {
+            pid: id,
+            from_state: from.as_str().into(),
+            to_state: to.as_str().into(),
+            uid: uid,
+        };
+        diesel::insert(&record).into(post_transitions::table)
+            .execute(conn)?;
+        Ok(post)
+    })
+}
+
+
+/// Moves post `id` to `to`; fails with `InvalidTransition` if its current
+/// state does not allow that. Use `schedule` to schedule a post; without a
+/// time this fails with `MissingParameter`.
+pub fn transition(conn: &PgConnection, id: i32, to: State, uid: Option<i32>) -> DBResult<Post> {
+    move_to(conn, id, to, None, uid, UTC::now().naive_utc())
+}
+
+
+pub fn publish(conn: &PgConnection, id: i32, uid: Option<i32>) -> DBResult<Post> {
+    transition(conn, id, State::Published, uid)
+}
+
+
+/// Schedules post `id` to go live at `at`.
+pub fn schedule(conn: &PgConnection, id: i32, at: NaiveDateTime, uid: Option<i32>) -> DBResult<Post> {
+    move_to(conn, id, State::Scheduled, Some(at), uid, UTC::now().naive_utc())
+}
+
+
+/// Takes scheduled post `id` back to a draft. Fails with `InvalidTransition`
+/// from `published` once it is live, since it then has to be unpublished.
+pub fn unschedule(conn: &PgConnection, id: i32, uid: Option<i32>) -> DBResult<Post> {
+    use schema::posts;
+
+    let now = UTC::now().naive_utc();
+    conn.transaction(|| {
+        let post = posts::table.find(id).filter(posts::deleted.eq(false)).first::<Post>(conn)?;
+        let from = State::parse(&post.state).expect("Invalid post state");
+        if is_live(&post, now) {
+            return Err(Error::InvalidTransition(State::Published.as_str(), State::Draft.as_str()));
+        }
+        if from != State::Scheduled {
+            return Err(Error::InvalidTransition(from.as_str(), State::Draft.as_str()));
+        }
+        move_to(conn, id, State::Draft, None, uid, now)
+    })
+}
+
+
+/// Publishes the scheduled posts whose `publish_at` is not after `now`, recording
+/// `now` as when they went live, and returns them.
+pub fn mark_live(conn: &PgConnection, now: NaiveDateTime) -> DBResult<Vec<Post>> {
     use schema::posts::dsl;
 
-    diesel::update(dsl::posts.find(id))
-        .set(dsl::published.eq(true))
-        .get_result(conn)
-        .map(|post| post)
-        .map_err(|e| match e {
-            DieselError::NotFound => Error::RecordNotFound,
-            _ => Error::DatabaseError
-        })
+    let ids = dsl::posts
+        .filter(dsl::state.eq(State::Scheduled.as_str()))
+        .filter(dsl::deleted.eq(false))
+        .filter(dsl::publish_at.le(now))
+        .select(dsl::id)
+        .load::<i32>(conn)?;
+    let mut posts = Vec::new();
+    for id in ids {
+        match move_to(conn, id, State::Published, None, None, now) {
+            Ok(post) => posts.push(post),
+            // Moved or deleted meanwhile
+            Err(Error::RecordNotFound) | Err(Error::InvalidTransition(..)) => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(posts)
 }
 
 
-pub fn delete(conn: &PgConnection, id: i32) -> DBResult<usize> {
+/// State changes of post `id`, oldest first.
+pub fn transitions(conn: &PgConnection, id: i32) -> DBResult<Vec<PostTransition>> {
+    use schema::post_transitions::dsl;
+
+    dsl::post_transitions.filter(dsl::pid.eq(id))
+        .order(dsl::id.asc())
+        .load::<PostTransition>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Moves post `id` to the trash; with `version`, only that version of it.
+pub fn delete(conn: &PgConnection, id: i32, version: Option<i64>) -> DBResult<usize> {
     use schema::posts::dsl;
 
-    diesel::update(dsl::posts.find(id))
-            .set(dsl::deleted.eq(true))
-            .execute(conn)
-            .map(|num| num)
-            .map_err(|e| match e {
-                DieselError::NotFound => Error::RecordNotFound,
-                _ => Error::DatabaseError
-            })
+    let now = UTC::now().naive_utc();
+    conn.transaction(|| {
+        let current = dsl::posts.find(id).filter(dsl::deleted.eq(false)).first::<Post>(conn)?;
+        check_version(current.last_edited, version)?;
+        match diesel::update(dsl::posts.find(id)
+                             .filter(dsl::deleted.eq(false))
+                             .filter(dsl::last_edited.eq(current.last_edited)))
+            .set((dsl::deleted.eq(true), dsl::deleted_at.eq(now)))
+            .execute(conn)? {
+            0 => Err(changed(conn, id)),
+            n => Ok(n)
+        }
+    })
 }
 
 
-pub fn purge(conn: &PgConnection) -> DBResult<usize> {
+/// Deleted posts, most recently deleted first.
+pub fn trash(conn: &PgConnection) -> DBResult<Vec<Post>> {
+    use schema::posts::dsl;
+
+    dsl::posts.filter(dsl::deleted.eq(true))
+        .order((dsl::deleted_at.desc(), dsl::id.desc()))
+        .load::<Post>(conn)
+        .map_err(Error::from)
+}
+
+
+pub fn restore(conn: &PgConnection, id: i32) -> DBResult<Post> {
+    use schema::posts::dsl;
+
+    diesel::update(dsl::posts.find(id).filter(dsl::deleted.eq(true)))
+            .set((dsl::deleted.eq(false), dsl::deleted_at.eq(None::<NaiveDateTime>)))
+            .get_result(conn)
+            .map(|post| post)
+            .map_err(Error::from)
+}
+
+
+/// Locks whichever of posts `ids` are deleted, so that they cannot be
+/// restored meanwhile, and returns their ids.
+fn lock_deleted(conn: &PgConnection, ids: &[i32]) -> DBResult<Vec<i32>> {
+    // Diesel cannot say FOR UPDATE; the ids are numbers, so inlining them is safe.
+    let list: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    sql::<Integer>(&format!("SELECT id FROM posts WHERE id IN ({}) AND deleted FOR UPDATE", list.join(",")))
+        .load::<i32>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Purges whichever of posts `ids` are deleted along with their comments;
+/// fails with `RecordNotFound` if none are.
+fn purge_ids(conn: &PgConnection, ids: Vec<i32>) -> DBResult<usize> {
     use schema::posts::dsl;
 
-    diesel::delete(dsl::posts.filter(dsl::deleted.eq(true)))
+    if ids.is_empty() {
+        return Err(Error::RecordNotFound);
+    }
+    conn.transaction(|| {
+        let deleted = lock_deleted(conn, &ids)?;
+        if deleted.is_empty() {
+            return Err(Error::RecordNotFound);
+        }
+        comment::purge_for_posts(conn, &deleted)?;
+        tag::untag(conn, &deleted)?;
+        diesel::delete(dsl::posts.filter(dsl::id.eq_any(deleted)))
             .execute(conn)
-            .map(|num| num)
-            .map_err(|e| match e {
-                DieselError::NotFound => Error::RecordNotFound, // FIXME: necessary?
-                _ => Error::DatabaseError
-            })
+            .map_err(Error::from)
+    })
+}
+
+
+/// Purges posts `ids` when there may be none to purge.
+fn purge_any(conn: &PgConnection, ids: Vec<i32>) -> DBResult<usize> {
+    match purge_ids(conn, ids) {
+        Err(Error::RecordNotFound) => Ok(0),
+        result => result,
+    }
+}
+
+
+fn deleted_ids(conn: &PgConnection, before: Option<NaiveDateTime>) -> DBResult<Vec<i32>> {
+    use schema::posts::dsl;
+
+    let mut query = dsl::posts.filter(dsl::deleted.eq(true)).select(dsl::id).into_boxed();
+    if let Some(before) = before {
+        query = query.filter(dsl::deleted_at.lt(before));
+    }
+    query.load::<i32>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Empties the post trash.
+pub fn purge(conn: &PgConnection) -> DBResult<usize> {
+    let ids = deleted_ids(conn, None)?;
+    purge_any(conn, ids)
+}
+
+
+/// Purges posts deleted before `before`.
+pub fn purge_before(conn: &PgConnection, before: NaiveDateTime) -> DBResult<usize> {
+    let ids = deleted_ids(conn, Some(before))?;
+    purge_any(conn, ids)
+}
+
+
+/// Purges one deleted post.
+pub fn purge_one(conn: &PgConnection, id: i32) -> DBResult<usize> {
+    purge_ids(conn, vec![id])
 }
 
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use db::visitor;
     use std::{thread, time};
 
     #[test]
@@ -146,62 +522,152 @@ mod test {
         let cats = vec!["tag1".into(), "tag2".into()];
         let body = "body1";
 
-        let post = create(conn, title, Some(&cats), body).unwrap();
-        assert!(post.title == title && post.category == "tag1,tag2"
-                && post.body == body && post.published == false);
+        let post = create(conn, title, Some(&cats), body, None).unwrap();
+        assert!(post.title == title && post.body == body && post.state == "draft");
+        assert!(tag::for_post(conn, post.id).unwrap() == cats);
         assert!(post.created == post.last_edited);
 
         let post_id = post.id;
 
         // Retrieve draft
-        let posts = get_published(conn, Some(post_id));
+        let posts = get_published(conn, Some(post_id)).unwrap();
         assert!(posts.len() == 0);
 
         // Update
         let title = "title2";
         let body = "body2";
 
-        let post = update(conn, post_id, title, None, body).unwrap();
+        let post = update(conn, post_id, title, None, body, None, None).unwrap();
         println!("created: {:?}, updated: {:?}", post.created, post.last_edited);
-        assert!(post.title == title && post.category == ""
-                && post.body == body && post.published == false);
+        assert!(post.title == title && post.body == body && post.state == "draft");
+        assert!(tag::for_post(conn, post.id).unwrap().is_empty());
         assert!(post.created < post.last_edited);
         assert!(post.last_edited.signed_duration_since(post.created) < Duration::milliseconds(500));
 
-        // Publish
-        let post = publish(conn, post_id).unwrap();
-        assert!(post.published);
+        // Stale version
+        let current = version(post.last_edited);
+        assert!(update(conn, post_id, "stale", None, body, None, Some(current - 1)).err()
+                == Some(Error::VersionMismatch(current)));
+        assert!(delete(conn, post_id, Some(current - 1)).err() == Some(Error::VersionMismatch(current)));
+        assert!(update(conn, post_id, title, None, body, None, Some(current)).unwrap().last_edited > post.last_edited);
+
+        // Review and schedule; whole seconds so the stored time compares equal
+        let now = NaiveDateTime::from_timestamp(UTC::now().timestamp(), 0);
+        assert!(transition(conn, post_id, State::Review, None).unwrap().state == "review");
+        assert!(transition(conn, post_id, State::Scheduled, None).err()
+                == Some(Error::MissingParameter("publish_at")));
+        let post = schedule(conn, post_id, now + Duration::hours(1), None).unwrap();
+        assert!(post.state == "scheduled" && post.published_at.is_none());
+        assert!(get_published(conn, Some(post_id)).unwrap().len() == 0);
+        assert!(mark_live(conn, now).unwrap().iter().all(|p| p.id != post_id));
+        let live = mark_live(conn, now + Duration::hours(2)).unwrap();
+        assert!(live.iter().any(|p| p.id == post_id && p.published_at == Some(now + Duration::hours(2))));
+        assert!(get_published(conn, Some(post_id)).unwrap().len() == 1);
+
+        // Unpublish a scheduled post that went live before `mark_live` ran
+        let early = create(conn, "early", None, "body", None).unwrap();
+        schedule(conn, early.id, now - Duration::hours(1), None).unwrap();
+        assert!(is_live(&get(conn, Some(early.id), false, false).unwrap()[0], now));
+        assert!(unschedule(conn, early.id, None).err() == Some(Error::InvalidTransition("published", "draft")));
+        let early = transition(conn, early.id, State::Unpublished, None).unwrap();
+        assert!(unschedule(conn, early.id, None).err() == Some(Error::InvalidTransition("unpublished", "draft")));
+        assert!(early.state == "unpublished" && early.published_at == Some(now - Duration::hours(1)));
+        assert!(!is_live(&early, now));
+        delete(conn, early.id, None).unwrap();
+        purge_one(conn, early.id).unwrap();
+
+        // Unpublish, back to draft and publish
+        assert!(publish(conn, post_id, None).err() == Some(Error::InvalidTransition("published", "published")));
+        let post = transition(conn, post_id, State::Unpublished, None).unwrap();
+        assert!(post.state == "unpublished" && post.published_at == Some(now + Duration::hours(2)));
+        assert!(get_published(conn, Some(post_id)).unwrap().len() == 0);
+        assert!(transition(conn, post_id, State::Draft, None).unwrap().published_at.is_none());
+        let post = publish(conn, post_id, None).unwrap();
+        assert!(post.state == "published" && post.published_at.is_some());
+        let moves: Vec<String> = transitions(conn, post_id).unwrap().iter()
+            .map(|t| format!("{}>{}", t.from_state, t.to_state)).collect();
+        assert!(moves == vec!["draft>review", "review>scheduled", "scheduled>published",
+                              "published>unpublished", "unpublished>draft", "draft>published"],
+                "transitions: {:?}", moves);
 
         // Retrieve published
-        let ref post = get(conn, Some(post_id), false, false)[0];
-        assert!(post.title == title && post.category == ""
-                && post.body == body && post.published == true);
+        let ref post = get(conn, Some(post_id), false, false).unwrap()[0];
+        assert!(post.title == title && post.body == body && post.state == "published");
 
         // Delete
-        let num = delete(conn, post.id).unwrap();
+        let num = delete(conn, post.id, None).unwrap();
         assert!(num == 1);
-        let num = purge(conn).unwrap();
+        assert!(trash(conn).unwrap().iter().any(|p| p.id == post_id && p.deleted_at.is_some()));
+        assert!(!restore(conn, post_id).unwrap().deleted);
+        assert!(restore(conn, post_id).err() == Some(Error::RecordNotFound));
+        assert!(purge_one(conn, post_id).err() == Some(Error::RecordNotFound));
+
+        // Purge with comments
+        let reader = visitor::create(conn, "reader", "post@test.com", None).unwrap();
+        let cmt = comment::create(conn, post_id, reader.id, "hi", None, comment::Status::Approved, None).unwrap();
+        assert!(purge_one(conn, post_id).err() == Some(Error::RecordNotFound));
+        assert!(comment::get_many(conn, &[cmt.id]).unwrap().len() == 1);
+        delete(conn, post_id, None).unwrap();
+        let num = purge_one(conn, post_id).unwrap();
         assert!(num == 1, "purged: {} != 1", num);
+        visitor::delete(conn, reader.id, visitor::Cascade::Refuse).unwrap();
+        visitor::purge(conn).unwrap();
 
         // Wait for db commit; FIXME: use transaction
         let cent_millis = time::Duration::from_millis(100);
         thread::sleep(cent_millis);
 
         // Batch retrieve
-        let pv1 = get_published(conn, None);
-        let post1 = create(conn, "t1", Some(&cats), "b1").unwrap();
-        let post2 = create(conn, "t2", None, "b2").unwrap();
-        let pv2 = get_published(conn, None);
+        let pv1 = get_published(conn, None).unwrap();
+        let post1 = create(conn, "t1", Some(&cats), "b1", None).unwrap();
+        let post2 = create(conn, "t2", None, "b2", None).unwrap();
+        let pv2 = get_published(conn, None).unwrap();
         assert!(pv2.len() == pv1.len(), "pv1: {:?}, pv2: {:?}", pv1, pv2);
-        let post1 = publish(conn, post1.id).unwrap();
-        let post2 = publish(conn, post2.id).unwrap();
-        let pv2 = get_published(conn, None);
+        let post1 = publish(conn, post1.id, None).unwrap();
+        let post2 = publish(conn, post2.id, None).unwrap();
+        let pv2 = get_published(conn, None).unwrap();
         assert!(pv2.len() == pv1.len() + 2);
-        let num = delete(conn, post1.id).unwrap();
+        let num = delete(conn, post1.id, None).unwrap();
         assert!(num == 1);
-        let num = delete(conn, post2.id).unwrap();
+        let num = delete(conn, post2.id, None).unwrap();
         assert!(num == 1);
         let num = purge(conn).unwrap();
         assert!(num == 2);
     }
+
+    #[test]
+    fn test_list() {
+        use db::DB_POOL;
+        use db::page::{Cursor, Position, Sort};
+
+        let ref conn = DB_POOL.get().unwrap();
+        let cats = vec!["list_tag".into()];
+        let ids: Vec<i32> = (0..3)
+            .map(|i| create(conn, &format!("t{}", i), Some(&cats), "b", None).unwrap().id)
+            .collect();
+
+        let filter = Filter { tag: Some("list_tag".into()), published: Some(false), ..Filter::default() };
+        let first = PageRequest::new(Sort::Oldest, Position::First, 2);
+        let page = list(conn, &filter, &first).unwrap();
+        assert!(page.total == 3 && page.prev.is_none());
+        assert!(page.items.iter().map(|p| p.id).collect::<Vec<_>>() == ids[..2].to_vec());
+
+        let cursor = Cursor::parse(page.next.as_ref().unwrap()).unwrap();
+        let second = PageRequest::new(Sort::Oldest, Position::After(cursor), 2);
+        let page = list(conn, &filter, &second).unwrap();
+        assert!(page.items.len() == 1 && page.items[0].id == ids[2] && page.next.is_none());
+
+        let cursor = Cursor::parse(page.prev.as_ref().unwrap()).unwrap();
+        let back = PageRequest::new(Sort::Oldest, Position::Before(cursor), 2);
+        let page = list(conn, &filter, &back).unwrap();
+        assert!(page.items.iter().map(|p| p.id).collect::<Vec<_>>() == ids[..2].to_vec());
+
+        let others = Filter { author: Some(0), ..filter };
+        assert!(list(conn, &others, &first).unwrap().total == 0);
+
+        for id in ids {
+            delete(conn, id, None).unwrap();
+        }
+        purge(conn).unwrap();
+    }
 }
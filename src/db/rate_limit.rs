@@ -0,0 +1,74 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::pg::upsert::*;
+
+// Timestamp
+use chrono::prelude::*;
+
+use schema::rate_limits;
+use models::Bucket;
+use db::{Error, DBResult};
+
+
+pub fn get(conn: &PgConnection, key: &str) -> DBResult<Option<Bucket>> {
+    rate_limits::table.find(key)
+        .first::<Bucket>(conn)
+        .optional()
+        .map_err(Error::from)
+}
+
+
+/// Creates a bucket unless another instance got there first; returns whether it did.
+pub fn create(conn: &PgConnection, key: &str, tokens: f64, updated: NaiveDateTime) -> DBResult<bool> {
+    let bucket = Bucket {
+        key: key.into(),
+        tokens: tokens,
+        updated: updated,
+    };
+
+    diesel::insert(&bucket.on_conflict_do_nothing()).into(rate_limits::table)
+        .execute(conn)
+        .map(|num| num == 1)
+        .map_err(Error::from)
+}
+
+
+/// Replaces `old` with the new bucket state unless another instance changed it
+/// in the meantime; returns whether it did.
+pub fn swap(conn: &PgConnection, old: &Bucket, tokens: f64, updated: NaiveDateTime) -> DBResult<bool> {
+    diesel::update(rate_limits::table.find(old.key.as_str())
+                       .filter(rate_limits::tokens.eq(old.tokens))
+                       .filter(rate_limits::updated.eq(old.updated)))
+        .set((rate_limits::tokens.eq(tokens), rate_limits::updated.eq(updated)))
+        .execute(conn)
+        .map(|num| num == 1)
+        .map_err(Error::from)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+
+        let key = "test_rate_limit";
+        let now = UTC::now().naive_utc();
+        assert!(get(conn, key).unwrap().is_none());
+        assert!(create(conn, key, 4.0, now).unwrap());
+        assert!(!create(conn, key, 4.0, now).unwrap());
+
+        let bucket = get(conn, key).unwrap().unwrap();
+        assert!(swap(conn, &bucket, 3.0, now).unwrap());
+        assert!(!swap(conn, &bucket, 2.0, now).unwrap());
+        assert!(get(conn, key).unwrap().unwrap().tokens == 3.0);
+
+        diesel::delete(rate_limits::table.find(key)).execute(conn).unwrap();
+    }
+}
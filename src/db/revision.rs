@@ -0,0 +1,85 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use schema::post_revisions;
+use models::{Post, PostRevision, NewPostRevision};
+use db::{Error, DBResult};
+
+
+/// Saves the current content of `post`, tagged `categories`, as its next
+/// revision, written by user `uid`. Call it in the transaction that changed
+/// the post, after the change, so the post row stays locked until the
+/// revision is in.
+pub fn record(conn: &PgConnection, post: &Post, categories: &[String], uid: Option<i32>) -> DBResult<PostRevision> {
+    let last = post_revisions::table
+        .filter(post_revisions::pid.eq(post.id))
+        .select(post_revisions::rev)
+        .order(post_revisions::rev.desc())
+        .first::<i32>(conn)
+        .optional()?;
+    let revision = NewPostRevision {
+        pid: post.id,
+        rev: last.unwrap_or(0) + 1,
+        title: post.title.clone(),
+        body: post.body.clone(),
+        uid: uid,
+        categories: categories.to_vec(),
+    };
+
+    diesel::insert(&revision).into(post_revisions::table)
+        .get_result(conn)
+        .map(|revision| revision)
+        .map_err(Error::from)
+}
+
+
+/// Revisions of post `pid`, newest first.
+pub fn for_post(conn: &PgConnection, pid: i32) -> DBResult<Vec<PostRevision>> {
+    post_revisions::table
+        .filter(post_revisions::pid.eq(pid))
+        .order(post_revisions::rev.desc())
+        .load::<PostRevision>(conn)
+        .map_err(Error::from)
+}
+
+
+pub fn get(conn: &PgConnection, pid: i32, rev: i32) -> DBResult<PostRevision> {
+    post_revisions::table
+        .filter(post_revisions::pid.eq(pid))
+        .filter(post_revisions::rev.eq(rev))
+        .first::<PostRevision>(conn)
+        .map_err(Error::from)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_revision() {
+        use db::{DB_POOL, post};
+        let ref conn = DB_POOL.get().unwrap();
+
+        let cats = vec!["revision".into()];
+        let created = post::create(conn, "first", Some(&cats), "one\ntwo", None).unwrap();
+        post::update(conn, created.id, "second", None, "one\nthree", None, None).unwrap();
+        let revisions = for_post(conn, created.id).unwrap();
+        assert!(revisions.iter().map(|r| r.rev).collect::<Vec<_>>() == vec![2, 1]);
+        assert!(revisions[0].title == "second" && revisions[1].body == "one\ntwo");
+        assert!(get(conn, created.id, 1).unwrap().title == "first");
+        assert!(get(conn, created.id, 3).err() == Some(Error::RecordNotFound));
+
+        let restored = post::restore_revision(conn, created.id, 1, None).unwrap();
+        assert!(restored.rev == 3 && restored.title == "first" && restored.body == "one\ntwo");
+        assert!(restored.categories == cats && revisions[0].categories.is_empty());
+        assert!(post::get_all(conn).unwrap().iter().any(|p| p.id == created.id && p.title == "first"));
+
+        post::delete(conn, created.id, None).unwrap();
+        post::purge_one(conn, created.id).unwrap();
+        assert!(for_post(conn, created.id).unwrap().is_empty());
+    }
+}
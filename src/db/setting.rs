@@ -0,0 +1,55 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::pg::upsert::*;
+
+use schema::settings;
+use models::Setting;
+use db::{Error, DBResult};
+
+
+/// Site-wide settings stored as key/value pairs.
+pub fn get(conn: &PgConnection, key: &str) -> DBResult<Option<String>> {
+    settings::table.find(key)
+        .first::<Setting>(conn)
+        .optional()
+        .map(|setting| setting.map(|s| s.value))
+        .map_err(Error::from)
+}
+
+
+/// Creates or replaces a setting.
+pub fn set(conn: &PgConnection, key: &str, value: &str) -> DBResult<Setting> {
+    let setting = Setting {
+        key: key.into(),
+        value: value.into(),
+    };
+
+    diesel::insert(&setting.on_conflict(settings::key, do_update().set(settings::value.eq(excluded(settings::value)))))
+        .into(settings::table)
+        .get_result(conn)
+        .map(|setting| setting)
+        .map_err(Error::from)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_setting() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+
+        assert!(get(conn, "test_setting").unwrap().is_none());
+        set(conn, "test_setting", "a").unwrap();
+        let setting = set(conn, "test_setting", "b").unwrap();
+        assert!(setting.value == "b");
+        assert!(get(conn, "test_setting").unwrap() == Some("b".into()));
+
+        diesel::delete(settings::table.find("test_setting")).execute(conn).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::pg::upsert::*;
+
+use std::collections::HashMap;
+
+use schema::{comments, spam_corpus, spam_tokens};
+use models::{Comment, SpamToken};
+use db::{Error, DBResult};
+
+
+/// Which side of the spam model a comment was trained on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Label {
+    Spam,
+    Ham,
+}
+
+impl Label {
+    pub fn parse(s: &str) -> Option<Label> {
+        match s {
+            "spam" => Some(Label::Spam),
+            "ham" => Some(Label::Ham),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Label::Spam => "spam",
+            Label::Ham => "ham",
+        }
+    }
+}
+
+
+/// Number of comments trained as `(spam, ham)`.
+pub fn corpus(conn: &PgConnection) -> DBResult<(i32, i32)> {
+    let rows = spam_corpus::table.load::<(String, i32)>(conn)?;
+    let count = |label: Label| rows.iter()
+        .find(|&&(ref l, _)| l == label.as_str())
+        .map_or(0, |&(_, n)| n);
+    Ok((count(Label::Spam), count(Label::Ham)))
+}
+
+
+/// Loads `(spam, ham)` counts for the known tokens among `tokens`.
+pub fn counts(conn: &PgConnection, tokens: &[String]) -> DBResult<HashMap<String, (i32, i32)>> {
+    spam_tokens::table.filter(spam_tokens::token.eq_any(tokens.to_vec()))
+        .load::<SpamToken>(conn)
+        .map(|rows| rows.into_iter().map(|t| (t.token, (t.spam, t.ham))).collect())
+        .map_err(Error::from)
+}
+
+
+/// Adds one document's tokens to `label`, or takes them away again with `delta = -1`.
+fn adjust(conn: &PgConnection, tokens: &[String], label: Label, delta: i32) -> DBResult<()> {
+    diesel::update(spam_corpus::table.find(label.as_str()))
+        .set(spam_corpus::documents.eq(spam_corpus::documents + delta))
+        .execute(conn)?;
+    if tokens.is_empty() {
+        return Ok(());
+    }
+
+    let rows: Vec<SpamToken> = tokens.iter().map(|t| SpamToken {
+        token: t.clone(),
+        spam: if label == Label::Spam { delta } else { 0 },
+        ham: if label == Label::Ham { delta } else { 0 },
+    }).collect();
+    diesel::insert(&rows.on_conflict(spam_tokens::token, do_update().set((
+            spam_tokens::spam.eq(spam_tokens::spam + excluded(spam_tokens::spam)),
+            spam_tokens::ham.eq(spam_tokens::ham + excluded(spam_tokens::ham)),
+        ))))
+        .into(spam_tokens::table)
+        .execute(conn)
+        .map(|_| ())
+        .map_err(Error::from)
+}
+
+
+/// Records a moderator's verdict on `comment`, whose body yields `tokens`.
+///
+/// A comment counts towards at most one label; training it again under a
+/// different label, or under none, first undoes the earlier training.
+pub fn learn(conn: &PgConnection, comment: &Comment, tokens: &[String], label: Option<Label>) -> DBResult<()> {
+    let trained = comment.trained.as_ref().and_then(|t| Label::parse(t));
+    if trained == label {
+        return Ok(());
+    }
+
+    conn.transaction(|| {
+        if let Some(old) = trained {
+            adjust(conn, tokens, old, -1)?;
+        }
+        if let Some(new) = label {
+            adjust(conn, tokens, new, 1)?;
+        }
+        diesel::update(comments::table.find(comment.id))
+            .set(comments::trained.eq(label.map(|l| l.as_str())))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    })
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use db::{comment, post, visitor};
+
+    #[test]
+    fn test_learn() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+
+        let post = post::create(conn, "spam test", None, "body", None).unwrap();
+        let visitor = visitor::create(conn, "spammer", "spam@test.com", None).unwrap();
+        let cmt = comment::create(conn, post.id, visitor.id, "cheap pills", None,
+                                  comment::Status::Pending, None).unwrap();
+        let tokens: Vec<String> = vec!["test_learn_cheap".into(), "test_learn_pills".into()];
+
+        let (spam_docs, ham_docs) = corpus(conn).unwrap();
+        learn(conn, &cmt, &tokens, Some(Label::Spam)).unwrap();
+        assert!(corpus(conn).unwrap() == (spam_docs + 1, ham_docs));
+        assert!(counts(conn, &tokens).unwrap()["test_learn_pills"] == (1, 0));
+
+        // Retrain as ham
+        let cmt = comment::get_many(conn, &[cmt.id]).unwrap().pop().unwrap();
+        assert!(cmt.trained == Some("spam".into()));
+        learn(conn, &cmt, &tokens, Some(Label::Ham)).unwrap();
+        assert!(corpus(conn).unwrap() == (spam_docs, ham_docs + 1));
+        assert!(counts(conn, &tokens).unwrap()["test_learn_cheap"] == (0, 1));
+
+        // Forget
+        let cmt = comment::get_many(conn, &[cmt.id]).unwrap().pop().unwrap();
+        learn(conn, &cmt, &tokens, None).unwrap();
+        assert!(corpus(conn).unwrap() == (spam_docs, ham_docs));
+
+        diesel::delete(spam_tokens::table.filter(spam_tokens::token.eq_any(tokens))).execute(conn).unwrap();
+        comment::delete(conn, cmt.id, None).unwrap();
+        comment::purge(conn).unwrap();
+        visitor::delete(conn, visitor.id, visitor::Cascade::Refuse).unwrap();
+        visitor::purge(conn).unwrap();
+        post::delete(conn, post.id, None).unwrap();
+        post::purge(conn).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::pg::upsert::*;
+
+use schema::subscriptions;
+use models::{Subscription, NewSubscription};
+use db::{Error, DBResult};
+
+
+/// Subscribes visitor `vid` to new comments on post `pid`; returns false if
+/// they already were.
+pub fn subscribe(conn: &PgConnection, vid: i32, pid: i32) -> DBResult<bool> {
+    let new_sub = NewSubscription {
+        vid: vid,
+        pid: pid,
+    };
+
+    diesel::insert(&new_sub.on_conflict_do_nothing()).into(subscriptions::table)
+        .execute(conn)
+        .map(|num| num == 1)
+        .map_err(Error::from)
+}
+
+
+pub fn unsubscribe(conn: &PgConnection, vid: i32, pid: i32) -> DBResult<usize> {
+    diesel::delete(subscriptions::table
+                       .filter(subscriptions::vid.eq(vid))
+                       .filter(subscriptions::pid.eq(pid)))
+            .execute(conn)
+            .map_err(Error::from)
+            .and_then(|num| match num {
+                0 => Err(Error::RecordNotFound),
+                n => Ok(n)
+            })
+}
+
+
+/// Ids of the visitors subscribed to post `pid`.
+pub fn subscribers(conn: &PgConnection, pid: i32) -> DBResult<Vec<i32>> {
+    subscriptions::table
+        .filter(subscriptions::pid.eq(pid))
+        .select(subscriptions::vid)
+        .load::<i32>(conn)
+        .map_err(Error::from)
+}
+
+
+pub fn for_visitor(conn: &PgConnection, vid: i32) -> DBResult<Vec<Subscription>> {
+    subscriptions::table
+        .filter(subscriptions::vid.eq(vid))
+        .load::<Subscription>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Drops every subscription of visitor `vid`; returns how many there were.
+pub fn unsubscribe_all(conn: &PgConnection, vid: i32) -> DBResult<usize> {
+    diesel::delete(subscriptions::table.filter(subscriptions::vid.eq(vid)))
+            .execute(conn)
+            .map(|num| num)
+            .map_err(Error::from)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use db::{post, visitor};
+
+    #[test]
+    fn test_subscription() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+
+        let post = post::create(conn, "subscribed", None, "body", None).unwrap();
+        let visitor = visitor::create(conn, "subscriber", "subscriber@test.com", None).unwrap();
+        assert!(subscribe(conn, visitor.id, post.id).unwrap());
+        assert!(!subscribe(conn, visitor.id, post.id).unwrap());
+        assert!(subscribers(conn, post.id).unwrap() == vec![visitor.id]);
+        assert!(for_visitor(conn, visitor.id).unwrap()[0].pid == post.id);
+        assert!(subscribe(conn, visitor.id, -1).err() == Some(Error::ForeignKeyViolation));
+
+        assert!(unsubscribe(conn, visitor.id, post.id).unwrap() == 1);
+        assert!(unsubscribe(conn, visitor.id, post.id).err() == Some(Error::RecordNotFound));
+        assert!(subscribers(conn, post.id).unwrap().is_empty());
+
+        visitor::delete(conn, visitor.id, visitor::Cascade::Refuse).unwrap();
+        visitor::purge(conn).unwrap();
+        post::delete(conn, post.id, None).unwrap();
+        post::purge(conn).unwrap();
+    }
+}
@@ -0,0 +1,340 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::pg::upsert::*;
+use diesel::expression::dsl::sql;
+use diesel::types::{BigInt, Integer, VarChar};
+
+use std::collections::{HashMap, HashSet};
+
+use schema::{post_tags, tags};
+use models::{Post, PostView, PostTag, Tag, NewTag};
+use db::{Error, DBResult, post};
+use db::page::Page;
+
+
+/// URL form of a tag name: ASCII letters lowercased, `+` and `#` spelled out
+/// so that "C++" and "C#" differ from "C", runs of other ASCII characters
+/// turned into `-`; other characters are kept as they are.
+/// Names with the same slug are the same tag.
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    let mut gap = false;
+    for c in name.chars() {
+        let word = match c {
+            '+' => "plus",
+            '#' => "sharp",
+            _ => "",
+        };
+        if !word.is_empty() {
+            if !slug.is_empty() {
+                slug.push('-');
+            }
+            slug.push_str(word);
+            gap = true;
+            continue;
+        }
+        if (c as u32) < 0x80 && !c.is_alphanumeric() {
+            gap = true;
+            continue;
+        }
+        if gap && !slug.is_empty() {
+            slug.push('-');
+        }
+        gap = false;
+        if (c as u32) < 0x80 {
+            slug.extend(c.to_lowercase());
+        } else {
+            slug.push(c);
+        }
+    }
+    slug
+}
+
+
+pub fn get(conn: &PgConnection, slug: &str) -> DBResult<Tag> {
+    tags::table.filter(tags::slug.eq(slug))
+        .first::<Tag>(conn)
+        .map_err(Error::from)
+}
+
+
+/// The tag `name` belongs to, created on first use.
+fn find_or_create(conn: &PgConnection, name: &str, slug: &str) -> DBResult<Tag> {
+    let new_tag = NewTag {
+        name: name.into(),
+        slug: slug.into(),
+    };
+    diesel::insert(&new_tag.on_conflict_do_nothing()).into(tags::table)
+        .execute(conn)?;
+    get(conn, slug)
+}
+
+
+/// Deletes whichever of tags `tids` no post carries any more.
+fn delete_orphans(conn: &PgConnection, tids: Vec<i32>) -> DBResult<usize> {
+    let used: HashSet<i32> = post_tags::table
+        .filter(post_tags::tid.eq_any(tids.clone()))
+        .select(post_tags::tid)
+        .load::<i32>(conn)?
+        .into_iter()
+        .collect();
+    let orphans: Vec<i32> = tids.into_iter().filter(|tid| !used.contains(tid)).collect();
+    diesel::delete(tags::table.filter(tags::id.eq_any(orphans)))
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+
+/// Takes every tag off posts `pids`, deleting the tags left unused.
+pub fn untag(conn: &PgConnection, pids: &[i32]) -> DBResult<usize> {
+    let tids = diesel::delete(post_tags::table.filter(post_tags::pid.eq_any(pids.to_vec())))
+        .returning(post_tags::tid)
+        .get_results::<i32>(conn)?;
+    delete_orphans(conn, tids)
+}
+
+
+/// Replaces the tags of post `pid` with `names`, in that order; blank names
+/// and repeats are dropped, as are tags no post carries afterwards.
+pub fn set_for_post(conn: &PgConnection, pid: i32, names: &[String]) -> DBResult<()> {
+    conn.transaction(|| {
+        let old = diesel::delete(post_tags::table.filter(post_tags::pid.eq(pid)))
+            .returning(post_tags::tid)
+            .get_results::<i32>(conn)?;
+        let mut seen = HashSet::new();
+        for name in names {
+            let name = name.trim();
+            let slug = slugify(name);
+            if slug.is_empty() || !seen.insert(slug.clone()) {
+                continue;
+            }
+            let tag = find_or_create(conn, name, &slug)?;
+            let link = PostTag { pid: pid, tid: tag.id, position: seen.len() as i32 };
+            diesel::insert(&link).into(post_tags::table)
+                .execute(conn)?;
+        }
+        delete_orphans(conn, old)?;
+        Ok(())
+    })
+}
+
+
+/// Tag names of each of posts `pids`, in order.
+pub fn for_posts(conn: &PgConnection, pids: &[i32]) -> DBResult<HashMap<i32, Vec<String>>> {
+    let links = post_tags::table
+        .filter(post_tags::pid.eq_any(pids.to_vec()))
+        .order((post_tags::pid.asc(), post_tags::position.asc()))
+        .load::<PostTag>(conn)?;
+    let tids: Vec<i32> = links.iter().map(|l| l.tid).collect();
+    let names: HashMap<i32, String> = tags::table
+        .filter(tags::id.eq_any(tids))
+        .load::<Tag>(conn)?
+        .into_iter()
+        .map(|t| (t.id, t.name))
+        .collect();
+
+    let mut tagged = HashMap::new();
+    for link in links {
+        if let Some(name) = names.get(&link.tid) {
+            tagged.entry(link.pid).or_insert_with(Vec::new).push(name.clone());
+        }
+    }
+    Ok(tagged)
+}
+
+
+pub fn for_post(conn: &PgConnection, pid: i32) -> DBResult<Vec<String>> {
+    for_posts(conn, &[pid]).map(|mut tagged| tagged.remove(&pid).unwrap_or(vec![]))
+}
+
+
+/// Pairs posts with their tags for sending to clients.
+pub fn view(conn: &PgConnection, posts: Vec<Post>) -> DBResult<Vec<PostView>> {
+    let ids: Vec<i32> = posts.iter().map(|p| p.id).collect();
+    let mut tagged = for_posts(conn, &ids)?;
+    Ok(posts.into_iter()
+       .map(|p| { let names = tagged.remove(&p.id).unwrap_or(vec![]); PostView::new(p, names) })
+       .collect())
+}
+
+
+pub fn view_page(conn: &PgConnection, page: Page<Post>) -> DBResult<Page<PostView>> {
+    let ids: Vec<i32> = page.items.iter().map(|p| p.id).collect();
+    let mut tagged = for_posts(conn, &ids)?;
+    Ok(page.map(|p| { let names = tagged.remove(&p.id).unwrap_or(vec![]); PostView::new(p, names) }))
+}
+
+
+#[derive(Serialize)]
+pub struct TagCount {
+    pub id: i32,
+    pub name: String,
+    pub slug: String,
+    /// Live posts with the tag.
+    pub posts: i64,
+}
+
+/// Every tag by name, with how many live posts carry it.
+pub fn all(conn: &PgConnection) -> DBResult<Vec<TagCount>> {
+    // Diesel cannot group; live as in `post::filtered`.
+    let query = format!("SELECT t.id, t.name, t.slug, count(p.id) FROM tags t \
+                         LEFT JOIN post_tags pt ON pt.tid = t.id \
+                         LEFT JOIN posts p ON p.id = pt.pid AND NOT p.deleted \
+                             AND (p.state = '{}' OR (p.state = '{}' AND p.publish_at <= (now() AT TIME ZONE 'UTC'))) \
+                         GROUP BY t.id ORDER BY t.name",
+                        post::State::Published.as_str(), post::State::Scheduled.as_str());
+    sql::<(Integer, VarChar, VarChar, BigInt)>(&query)
+        .load::<(i32, String, String, i64)>(conn)
+        .map(|rows| rows.into_iter().map(|(id, name, slug, posts)| TagCount {
+            id: id,
+            name: name,
+            slug: slug,
+            posts: posts,
+        }).collect())
+        .map_err(Error::from)
+}
+
+
+/// Ids of the posts carrying tag `tid`.
+fn tagged_posts(conn: &PgConnection, tid: i32) -> DBResult<Vec<i32>> {
+    post_tags::table
+        .filter(post_tags::tid.eq(tid))
+        .select(post_tags::pid)
+        .load::<i32>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Numbers the tags of post `pid` from 1 again, keeping their order.
+fn renumber(conn: &PgConnection, pid: i32) -> DBResult<()> {
+    let links = post_tags::table
+        .filter(post_tags::pid.eq(pid))
+        .order((post_tags::position.asc(), post_tags::tid.asc()))
+        .load::<PostTag>(conn)?;
+    for (i, link) in links.into_iter().enumerate() {
+        diesel::update(post_tags::table.filter(post_tags::pid.eq(pid)).filter(post_tags::tid.eq(link.tid)))
+            .set(post_tags::position.eq(i as i32 + 1))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+
+/// Renames the tag `slug`, recording a revision by user `uid` of each post
+/// carrying it; fails with `UniqueViolation` if the new name belongs to
+/// another tag, which calls for `merge` instead.
+pub fn rename(conn: &PgConnection, slug: &str, name: &str, uid: Option<i32>) -> DBResult<Tag> {
+    let name = name.trim();
+    conn.transaction(|| {
+        let tag = diesel::update(tags::table.filter(tags::slug.eq(slug)))
+            .set((tags::name.eq(name), tags::slug.eq(slugify(name))))
+            .get_result::<Tag>(conn)?;
+        post::retagged(conn, &tagged_posts(conn, tag.id)?, uid)?;
+        Ok(tag)
+    })
+}
+
+
+/// Moves the posts of tag `from` to tag `into` and removes `from`, recording
+/// a revision by user `uid` of each post moved.
+pub fn merge(conn: &PgConnection, from: &str, into: &str, uid: Option<i32>) -> DBResult<Tag> {
+    conn.transaction(|| {
+        let source = get(conn, from)?;
+        let target = get(conn, into)?;
+        if source.id == target.id {
+            return Ok(target);
+        }
+        let links = post_tags::table
+            .filter(post_tags::tid.eq(source.id))
+            .load::<PostTag>(conn)?;
+        for link in &links {
+            let moved = PostTag { pid: link.pid, tid: target.id, position: link.position };
+            diesel::insert(&moved.on_conflict_do_nothing()).into(post_tags::table)
+                .execute(conn)?;
+        }
+        diesel::delete(tags::table.find(source.id))
+            .execute(conn)?;
+        let pids: Vec<i32> = links.iter().map(|l| l.pid).collect();
+        for &pid in &pids {
+            renumber(conn, pid)?;
+        }
+        post::retagged(conn, &pids, uid)?;
+        Ok(target)
+    })
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use db::revision;
+
+    #[test]
+    fn test_slugify() {
+        assert!(slugify("Rust") == "rust");
+        assert!(slugify("  Web, Dev! ") == "web-dev");
+        assert!(slugify("C++ / Rust") == "c-plus-plus-rust");
+        assert!(slugify("C") == "c" && slugify("C++") == "c-plus-plus" && slugify("C#") == "c-sharp");
+        assert!(slugify("F#") == "f-sharp" && slugify("F") == "f" && slugify("+1") == "plus-1");
+        assert!(slugify("日记 2017") == "日记-2017");
+        assert!(slugify("--") == "");
+    }
+
+    #[test]
+    fn test_tag() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+
+        let names = vec!["Tag Test".into(), "tag-test".into(), "Other, Test".into(), " ".into()];
+        let post = post::create(conn, "tagged", Some(&names), "body", None).unwrap();
+        assert!(for_post(conn, post.id).unwrap() == vec!["Tag Test", "Other, Test"]);
+        assert!(get(conn, "other-test").unwrap().name == "Other, Test");
+
+        // Symbols keep tags apart
+        let langs = vec!["C Test".into(), "C++ Test".into(), "C# Test".into()];
+        let coded = post::create(conn, "langs", Some(&langs), "body", None).unwrap();
+        assert!(for_post(conn, coded.id).unwrap() == langs);
+        post::delete(conn, coded.id, None).unwrap();
+        post::purge_one(conn, coded.id).unwrap();
+        assert!(get(conn, "c-plus-plus-test").err() == Some(Error::RecordNotFound));
+
+        // Unused tags go
+        let more = vec!["Tag Test".into(), "Other, Test".into(), "Gone Test".into()];
+        post::update(conn, post.id, "tagged", Some(&more), "body", None, None).unwrap();
+        assert!(get(conn, "gone-test").is_ok());
+        post::update(conn, post.id, "tagged", Some(&names), "body", None, None).unwrap();
+        assert!(get(conn, "gone-test").err() == Some(Error::RecordNotFound));
+
+        // Rename and merge make revisions
+        let revisions = revision::for_post(conn, post.id).unwrap().len();
+        assert!(rename(conn, "tag-test", "Other test", None).err() == Some(Error::UniqueViolation));
+        assert!(rename(conn, "tag-test", "Tagged Test", None).unwrap().slug == "tagged-test");
+        let latest = revision::for_post(conn, post.id).unwrap();
+        assert!(latest.len() == revisions + 1 && latest[0].categories == vec!["Tagged Test", "Other, Test"]);
+        let other = post::create(conn, "other tagged", Some(&vec!["Other, Test".into(), "Third Test".into()]),
+                                 "body", None).unwrap();
+        let target = merge(conn, "tagged-test", "other-test", None).unwrap();
+        assert!(get(conn, "tagged-test").err() == Some(Error::RecordNotFound));
+        assert!(for_post(conn, post.id).unwrap() == vec!["Other, Test"]);
+        assert!(revision::for_post(conn, post.id).unwrap().len() == revisions + 2);
+        assert!(revision::for_post(conn, other.id).unwrap().len() == 1);
+        let positions: Vec<i32> = post_tags::table.filter(post_tags::pid.eq(post.id))
+            .select(post_tags::position).load(conn).unwrap();
+        assert!(positions == vec![1]);
+
+        // Counts
+        assert!(all(conn).unwrap().iter().any(|t| t.id == target.id && t.posts == 0));
+        post::publish(conn, post.id, None).unwrap();
+        assert!(all(conn).unwrap().iter().any(|t| t.id == target.id && t.posts == 1));
+
+        for id in vec![post.id, other.id] {
+            post::delete(conn, id, None).unwrap();
+            post::purge_one(conn, id).unwrap();
+        }
+        assert!(get(conn, "other-test").err() == Some(Error::RecordNotFound));
+        assert!(get(conn, "third-test").err() == Some(Error::RecordNotFound));
+    }
+}
@@ -0,0 +1,116 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::pg::upsert::*;
+use diesel::types::{BigInt, Bool};
+
+// Timestamp
+use chrono::prelude::*;
+
+use schema::scheduled_tasks;
+use models::TaskRun;
+use db::{Error, DBResult};
+
+sql_function!(pg_try_advisory_lock, pg_try_advisory_lock_t, (key: BigInt) -> Bool);
+sql_function!(pg_advisory_unlock, pg_advisory_unlock_t, (key: BigInt) -> Bool);
+
+
+/// Takes the session-level advisory lock `key` unless another connection
+/// holds it; returns whether it did. Only the same connection can `unlock` it.
+pub fn try_lock(conn: &PgConnection, key: i64) -> DBResult<bool> {
+    diesel::select(pg_try_advisory_lock(key))
+        .get_result::<bool>(conn)
+        .map_err(Error::from)
+}
+
+
+pub fn unlock(conn: &PgConnection, key: i64) -> DBResult<bool> {
+    diesel::select(pg_advisory_unlock(key))
+        .get_result::<bool>(conn)
+        .map_err(Error::from)
+}
+
+
+pub fn get(conn: &PgConnection, name: &str) -> DBResult<Option<TaskRun>> {
+    scheduled_tasks::table.find(name)
+        .first::<TaskRun>(conn)
+        .optional()
+        .map_err(Error::from)
+}
+
+
+/// Records that task `name` started running at `at`.
+pub fn started(conn: &PgConnection, name: &str, at: NaiveDateTime) -> DBResult<usize> {
+    let run = TaskRun {
+        name: name.into(),
+        last_started: Some(at),
+        last_finished: None,
+        last_result: None,
+        last_message: None,
+        runs: 0,
+    };
+
+    diesel::insert(&run.on_conflict(scheduled_tasks::name,
+                                    do_update().set(scheduled_tasks::last_started.eq(excluded(scheduled_tasks::last_started)))))
+        .into(scheduled_tasks::table)
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+
+/// Records how the run of task `name` that `started` recorded ended.
+pub fn finished(conn: &PgConnection, name: &str, at: NaiveDateTime, ok: bool, message: &str) -> DBResult<TaskRun> {
+    diesel::update(scheduled_tasks::table.find(name))
+        .set((scheduled_tasks::last_finished.eq(at),
+              scheduled_tasks::last_result.eq(if ok { "ok" } else { "error" }),
+              scheduled_tasks::last_message.eq(message),
+              scheduled_tasks::runs.eq(scheduled_tasks::runs + 1)))
+        .get_result(conn)
+        .map(|run| run)
+        .map_err(Error::from)
+}
+
+
+pub fn all(conn: &PgConnection) -> DBResult<Vec<TaskRun>> {
+    scheduled_tasks::table
+        .order(scheduled_tasks::name.asc())
+        .load::<TaskRun>(conn)
+        .map_err(Error::from)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_task() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+        let ref other = DB_POOL.get().unwrap();
+
+        // Lock
+        let key = 0x7e57_7a5c;
+        assert!(try_lock(conn, key).unwrap());
+        assert!(!try_lock(other, key).unwrap());
+        assert!(unlock(conn, key).unwrap());
+        assert!(try_lock(other, key).unwrap());
+        assert!(unlock(other, key).unwrap());
+
+        // Runs
+        let name = "test_task";
+        let now = UTC::now().naive_utc();
+        started(conn, name, now).unwrap();
+        let run = finished(conn, name, now, false, "boom").unwrap();
+        assert!(run.runs == 1 && run.last_result == Some("error".into()) && run.last_message == Some("boom".into()));
+        started(conn, name, now).unwrap();
+        let run = finished(conn, name, now, true, "done").unwrap();
+        assert!(run.runs == 2 && run.last_result == Some("ok".into()));
+        assert!(get(conn, name).unwrap().unwrap().last_started == Some(now));
+        assert!(all(conn).unwrap().iter().any(|r| r.name == name));
+
+        diesel::delete(scheduled_tasks::table.find(name)).execute(conn).unwrap();
+    }
+}
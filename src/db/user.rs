@@ -0,0 +1,201 @@
+// DB ORM
+use diesel;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+// Timestamp
+use chrono::prelude::*;
+
+use schema::{users, sessions};
+use models::{User, NewUser, Session, NewSession};
+use db::{Error, DBResult};
+
+
+/// Creates a user; `password` must already be hashed.
+pub fn create(conn: &PgConnection, name: &str, password: &str, role: &str) -> DBResult<User> {
+    let new_user = NewUser {
+        name: name.into(),
+        password: password.into(),
+        role: role.into(),
+    };
+
+    diesel::insert(&new_user).into(users::table)
+        .get_result(conn)
+        .map(|user| user)
+        .map_err(Error::from)
+}
+
+
+pub fn get(conn: &PgConnection, id: Option<i32>) -> DBResult<Vec<User>> {
+    let mut query = users::table.into_boxed();
+    if let Some(uid) = id {
+        query = query.filter(users::id.eq(uid));
+    }
+
+    query.load::<User>(conn)
+        .map_err(Error::from)
+}
+
+
+pub fn count_role(conn: &PgConnection, role: &str) -> DBResult<i64> {
+    users::table.filter(users::role.eq(role))
+        .count()
+        .get_result(conn)
+        .map_err(Error::from)
+}
+
+
+pub fn find_by_name(conn: &PgConnection, name: &str) -> DBResult<User> {
+    users::table.filter(users::name.eq(name))
+        .first(conn)
+        .map_err(Error::from)
+}
+
+
+/// Changes a user's role; if it differs, their sessions end so that they
+/// log in again under the new one.
+pub fn set_role(conn: &PgConnection, id: i32, role: &str) -> DBResult<User> {
+    conn.transaction(|| {
+        let current = users::table.find(id).first::<User>(conn)?;
+        if current.role != role {
+            end_sessions(conn, id)?;
+        }
+        diesel::update(users::table.find(id))
+                .set(users::role.eq(role))
+                .get_result(conn)
+                .map(|user| user)
+                .map_err(Error::from)
+    })
+}
+
+
+/// Replaces a user's password and ends their sessions; `password` must
+/// already be hashed.
+pub fn set_password(conn: &PgConnection, id: i32, password: &str) -> DBResult<User> {
+    conn.transaction(|| {
+        end_sessions(conn, id)?;
+        diesel::update(users::table.find(id))
+                .set(users::password.eq(password))
+                .get_result(conn)
+                .map(|user| user)
+                .map_err(Error::from)
+    })
+}
+
+
+pub fn delete(conn: &PgConnection, id: i32) -> DBResult<usize> {
+    diesel::delete(users::table.find(id))
+            .execute(conn)
+            .map_err(Error::from)
+            .and_then(|num| match num {
+                0 => Err(Error::RecordNotFound),
+                n => Ok(n)
+            })
+}
+
+
+/// Stores a session; `token` must already be hashed.
+pub fn create_session(conn: &PgConnection, uid: i32, token: &str, expires: NaiveDateTime) -> DBResult<Session> {
+    let new_session = NewSession {
+        token: token.into(),
+        uid: uid,
+        expires: expires,
+    };
+
+    diesel::insert(&new_session).into(sessions::table)
+        .get_result(conn)
+        .map(|session| session)
+        .map_err(Error::from)
+}
+
+
+/// Returns the user owning an unexpired session.
+pub fn find_session(conn: &PgConnection, token: &str) -> DBResult<User> {
+    let now = UTC::now().naive_utc();
+    let session = sessions::table.find(token)
+        .filter(sessions::expires.gt(now))
+        .first::<Session>(conn)
+        .map_err(Error::from)?;
+
+    users::table.find(session.uid)
+        .first(conn)
+        .map_err(Error::from)
+}
+
+
+pub fn delete_session(conn: &PgConnection, token: &str) -> DBResult<usize> {
+    diesel::delete(sessions::table.find(token))
+            .execute(conn)
+            .map(|num| num)
+            .map_err(Error::from)
+}
+
+
+/// Deletes every session of user `uid`.
+pub fn end_sessions(conn: &PgConnection, uid: i32) -> DBResult<usize> {
+    diesel::delete(sessions::table.filter(sessions::uid.eq(uid)))
+            .execute(conn)
+            .map(|num| num)
+            .map_err(Error::from)
+}
+
+
+pub fn purge_sessions(conn: &PgConnection) -> DBResult<usize> {
+    let now = UTC::now().naive_utc();
+    diesel::delete(sessions::table.filter(sessions::expires.le(now)))
+            .execute(conn)
+            .map(|num| num)
+            .map_err(Error::from)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_user() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+
+        // Create
+        let user = create(conn, "test_user", "hashed", "author").unwrap();
+        assert!(user.name == "test_user" && user.password == "hashed" && user.role == "author");
+        assert!(create(conn, "test_user", "other", "admin").err() == Some(Error::UniqueViolation));
+
+        let found = find_by_name(conn, "test_user").unwrap();
+        assert!(found.id == user.id);
+
+        // Update
+        let expires = UTC::now().naive_utc() + Duration::hours(1);
+        create_session(conn, user.id, "role_token", expires).unwrap();
+        let user = set_role(conn, user.id, "author").unwrap();
+        assert!(find_session(conn, "role_token").unwrap().id == user.id);
+        let user = set_role(conn, user.id, "editor").unwrap();
+        assert!(user.role == "editor");
+        assert!(find_session(conn, "role_token").err() == Some(Error::RecordNotFound));
+        create_session(conn, user.id, "password_token", expires).unwrap();
+        let user = set_password(conn, user.id, "rehashed").unwrap();
+        assert!(user.password == "rehashed");
+        assert!(find_session(conn, "password_token").err() == Some(Error::RecordNotFound));
+
+        // Sessions
+        create_session(conn, user.id, "live_token", expires).unwrap();
+        assert!(find_session(conn, "live_token").unwrap().id == user.id);
+
+        let expired = UTC::now().naive_utc() - Duration::hours(1);
+        create_session(conn, user.id, "dead_token", expired).unwrap();
+        assert!(find_session(conn, "dead_token").is_err());
+        assert!(purge_sessions(conn).unwrap() >= 1);
+
+        let num = delete_session(conn, "live_token").unwrap();
+        assert!(num == 1);
+        assert!(find_session(conn, "live_token").err() == Some(Error::RecordNotFound));
+
+        // Delete
+        assert!(delete(conn, user.id).unwrap() == 1);
+        assert!(get(conn, Some(user.id)).unwrap().len() == 0);
+    }
+}
@@ -1,71 +1,236 @@
 // DB ORM
 use diesel;
 use diesel::prelude::*;
-use diesel::result::Error as DieselError;
-use diesel::pg::PgConnection;
+use diesel::pg::{Pg, PgConnection};
+use diesel::types::VarChar;
 
-use db::{Error, DBResult};
+// Timestamp
+use chrono::prelude::*;
+
+use std::collections::HashSet;
+
+use db::{Error, DBResult, comment, subscription};
+use db::comment::Status;
+use db::page::{Page, PageRequest};
 use models::{Visitor, NewVisitor};
 
+sql_function!(lower, lower_t, (x: VarChar) -> VarChar);
+sql_function!(btrim, btrim_t, (x: VarChar) -> VarChar);
+
+
+/// Creates a visitor; fails with `UniqueViolation` if a visitor who is not
+/// deleted has the address, ignoring case and surrounding whitespace.
 pub fn create(conn: &PgConnection, name: &str, mail: &str, site: Option<String>) -> DBResult<Visitor> {
     use schema::visitors;
 
     let new_visitor = NewVisitor {
         name: name.into(),
-        mail: mail.into(),
+        mail: mail.trim().into(),
         site: site,
     };
 
     diesel::insert(&new_visitor).into(visitors::table)
         .get_result(conn)
         .map(|visitor| visitor)
-        .map_err(|_| Error::DatabaseError)
+        .map_err(Error::from)
 }
 
 
-pub fn get(conn:&PgConnection, id: Option<i32>) -> Vec<Visitor> {
+pub fn get(conn:&PgConnection, id: Option<i32>, non_deleted_only: bool) -> DBResult<Vec<Visitor>> {
     use schema::visitors;
 
     let mut query = visitors::table.into_boxed();
     if let Some(vid) = id {
         query = query.filter(visitors::id.eq(vid));
     }
+    if non_deleted_only {
+        query = query.filter(visitors::deleted.eq(false));
+    }
+
+    query.load::<Visitor>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Loads visitors by id, deleted ones included so that their comments can
+/// still be attributed.
+pub fn get_many(conn: &PgConnection, ids: &[i32]) -> DBResult<Vec<Visitor>> {
+    use schema::visitors;
+
+    visitors::table.filter(visitors::id.eq_any(ids.to_vec()))
+        .load::<Visitor>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Finds the visitor who is not deleted with address `mail`.
+pub fn find_by_mail(conn: &PgConnection, mail: &str) -> DBResult<Visitor> {
+    use schema::visitors;
 
-    let ret = query.load::<Visitor>(conn);
-    match ret {
-        Ok(v) => v,
-        _ => Vec::new()
+    visitors::table.filter(lower(btrim(visitors::mail)).eq(mail.trim().to_lowercase()))
+        .filter(visitors::deleted.eq(false))
+        .first::<Visitor>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Marks a visitor's address as verified, keeping the time it first was.
+pub fn verify(conn: &PgConnection, id: i32) -> DBResult<Visitor> {
+    use schema::visitors;
+
+    let now = UTC::now().naive_utc();
+    diesel::update(visitors::table.find(id).filter(visitors::verified_at.is_null()))
+        .set(visitors::verified_at.eq(now))
+        .execute(conn)?;
+    visitors::table.find(id)
+        .first::<Visitor>(conn)
+        .map_err(Error::from)
+}
+
+
+/// Filters for `list`.
+#[derive(Default)]
+pub struct Filter {
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+fn filtered(filter: &Filter) -> ::schema::visitors::BoxedQuery<'static, Pg> {
+    use schema::visitors;
+
+    let mut query = visitors::table.filter(visitors::deleted.eq(false)).into_boxed();
+    if let Some(since) = filter.since {
+        query = query.filter(visitors::created.ge(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(visitors::created.lt(until));
     }
+    query
 }
 
 
+/// Loads one page of visitors in `(created, id)` keyset order.
+pub fn list(conn: &PgConnection, filter: &Filter, page: &PageRequest) -> DBResult<Page<Visitor>> {
+    use schema::visitors;
+
+    keyset_page!(conn, visitors, Visitor, filtered(filter), page)
+}
+
+
+/// Updates a visitor; changing the address makes them unverified again.
 pub fn update(conn: &PgConnection, id: i32, name: &str, mail: &str, site: Option<String>) -> DBResult<Visitor> {
     use schema::visitors;
 
+    let current = visitors::table.find(id).filter(visitors::deleted.eq(false)).first::<Visitor>(conn)?;
+    let same_mail = current.mail.trim().to_lowercase() == mail.trim().to_lowercase();
     diesel::update(visitors::table.find(id))
             .set((visitors::name.eq(name),
-                  visitors::mail.eq(mail),
+                  visitors::mail.eq(mail.trim()),
                   visitors::site.eq(site),
+                  visitors::verified_at.eq(if same_mail { current.verified_at } else { None }),
+                  ))
+            .get_result(conn)
+            .map(|v| v)
+            .map_err(Error::from)
+}
+
+
+/// Scrubs a visitor's name, address and site while keeping the row, so
+/// their comments stay attributed to an anonymous placeholder.
+pub fn anonymize(conn: &PgConnection, id: i32) -> DBResult<Visitor> {
+    use schema::visitors;
+
+    diesel::update(visitors::table.find(id))
+            .set((visitors::name.eq("[erased]"),
+                  visitors::mail.eq(format!("erased-{}@invalid", id)),
+                  visitors::site.eq(None::<String>),
+                  visitors::verified_at.eq(None::<NaiveDateTime>),
                   ))
             .get_result(conn)
             .map(|v| v)
-            .map_err(|e| match e {
-                DieselError::NotFound => Error::RecordNotFound,
-                _ => Error::DatabaseError
-            })
+            .map_err(Error::from)
+}
+
+
+/// What deleting a visitor does to their comments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cascade {
+    /// Keep them, shown as by a deleted user.
+    Keep,
+    /// Soft-delete them along with the visitor.
+    Delete,
+    /// Refuse to delete a visitor who has comments.
+    Refuse,
+}
+
+impl Cascade {
+    pub fn parse(s: &str) -> Option<Cascade> {
+        match s {
+            "keep" => Some(Cascade::Keep),
+            "delete" => Some(Cascade::Delete),
+            "refuse" => Some(Cascade::Refuse),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Cascade::Keep => "keep",
+            Cascade::Delete => "delete",
+            Cascade::Refuse => "refuse",
+        }
+    }
 }
 
 
-pub fn delete(conn: &PgConnection, id: i32) -> DBResult<usize> {
+/// Soft-deletes a visitor and drops their subscriptions, leaving their
+/// address free for someone new; their comments are handled as `cascade`
+/// says. Refusing fails with `ForeignKeyViolation` if the visitor has
+/// comments that are not deleted.
+pub fn delete(conn: &PgConnection, id: i32, cascade: Cascade) -> DBResult<usize> {
     use schema::visitors;
 
-    diesel::delete(visitors::table.find(id))
+    conn.transaction(|| {
+        let comments: Vec<i32> = comment::get_by_visitor(conn, id)?.into_iter()
+            .filter(|c| c.status != Status::Deleted.as_str())
+            .map(|c| c.id)
+            .collect();
+        match cascade {
+            Cascade::Refuse if !comments.is_empty() => return Err(Error::ForeignKeyViolation),
+            Cascade::Delete if !comments.is_empty() => { comment::set_status(conn, &comments, Status::Deleted)?; },
+            _ => {},
+        }
+        subscription::unsubscribe_all(conn, id)?;
+
+        diesel::update(visitors::table.find(id).filter(visitors::deleted.eq(false)))
+                .set(visitors::deleted.eq(true))
+                .execute(conn)
+                .map_err(Error::from)
+                .and_then(|num| match num {
+                    0 => Err(Error::RecordNotFound),
+                    n => Ok(n)
+                })
+    })
+}
+
+
+/// Removes deleted visitors for good, except those with comments left.
+pub fn purge(conn: &PgConnection) -> DBResult<usize> {
+    use schema::{comments, visitors};
+
+    let deleted = visitors::table.filter(visitors::deleted.eq(true))
+        .select(visitors::id)
+        .load::<i32>(conn)?;
+    let commented: HashSet<i32> = comments::table.filter(comments::vid.eq_any(deleted.clone()))
+        .select(comments::vid)
+        .load::<i32>(conn)?
+        .into_iter()
+        .collect();
+    let ids: Vec<i32> = deleted.into_iter().filter(|id| !commented.contains(id)).collect();
+    diesel::delete(visitors::table.filter(visitors::id.eq_any(ids)))
             .execute(conn)
             .map(|num| num)
-            .map_err(|e| match e {
-                DieselError::NotFound => Error::RecordNotFound, // FIXME: necessary?
-                _ => Error::DatabaseError
-            })
+            .map_err(Error::from)
 }
 
 
@@ -73,6 +238,7 @@ pub fn delete(conn: &PgConnection, id: i32) -> DBResult<usize> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use db::post;
 
     #[test]
     fn test_visitor() {
@@ -91,9 +257,30 @@ mod test {
         let visitor_id = visitor.id;
 
         // Retrieve
-        let ref visitor = get(conn, Some(visitor_id))[0];
+        let ref visitor = get(conn, Some(visitor_id), true).unwrap()[0];
         assert!(visitor.name == name && visitor.mail == mail
                 && visitor.site == site);
+        let visitors = get_many(conn, &[visitor_id, -1]).unwrap();
+        assert!(visitors.len() == 1 && visitors[0].id == visitor_id);
+
+        // Same address
+        let duplicate = create(conn, "other", " TEST@test.com", None);
+        assert!(duplicate.err() == Some(Error::UniqueViolation));
+        assert!(find_by_mail(conn, "Test@Test.com ").unwrap().id == visitor_id);
+        assert!(find_by_mail(conn, "other@test.com").err() == Some(Error::RecordNotFound));
+        let other = create(conn, "other", "other@test.com", None).unwrap();
+        delete(conn, other.id, Cascade::Refuse).unwrap();
+        assert!(find_by_mail(conn, "other@test.com").err() == Some(Error::RecordNotFound));
+        let again = create(conn, "other", "other@test.com", None).unwrap();
+        assert!(find_by_mail(conn, "other@test.com").unwrap().id == again.id);
+        delete(conn, again.id, Cascade::Refuse).unwrap();
+
+        // Verify
+        let visitor = verify(conn, visitor_id).unwrap();
+        let verified_at = visitor.verified_at;
+        assert!(verified_at.is_some());
+        assert!(verify(conn, visitor_id).unwrap().verified_at == verified_at);
+        assert!(update(conn, visitor_id, name, "TEST@test.com", None).unwrap().verified_at == verified_at);
 
         // Update
         let name = "visitor2";
@@ -101,12 +288,30 @@ mod test {
         let site = None;
         let visitor = update(conn, visitor_id, name, mail, site).unwrap();
         assert!(visitor.name == name && visitor.mail == mail
-                && visitor.site == None);
+                && visitor.site == None && visitor.verified_at.is_none());
 
         // Delete
-        let num = delete(conn, visitor_id).unwrap();
+        let post = post::create(conn, "visitor test", None, "body", None).unwrap();
+        let cmt = comment::create(conn, post.id, visitor_id, "hi", None, Status::Approved, None).unwrap();
+        subscription::subscribe(conn, visitor_id, post.id).unwrap();
+        assert!(delete(conn, visitor_id, Cascade::Refuse).err() == Some(Error::ForeignKeyViolation));
+        assert!(get(conn, Some(visitor_id), true).unwrap().len() == 1);
+        let num = delete(conn, visitor_id, Cascade::Delete).unwrap();
         assert!(num == 1);
+        assert!(delete(conn, visitor_id, Cascade::Keep).err() == Some(Error::RecordNotFound));
+        assert!(get(conn, Some(visitor_id), true).unwrap().is_empty());
+        assert!(get_many(conn, &[visitor_id]).unwrap()[0].deleted);
+        assert!(comment::get_many(conn, &[cmt.id]).unwrap()[0].status == "deleted");
+        assert!(subscription::for_visitor(conn, visitor_id).unwrap().is_empty());
 
+        // Purge
+        purge(conn).unwrap();
+        assert!(get_many(conn, &[visitor_id, other.id, again.id]).unwrap().len() == 1);
+        comment::purge(conn).unwrap();
+        purge(conn).unwrap();
+        assert!(get_many(conn, &[visitor_id]).unwrap().is_empty());
+        post::delete(conn, post.id, None).unwrap();
+        post::purge(conn).unwrap();
     }
 }
 
@@ -0,0 +1,106 @@
+use std::cmp;
+use std::fmt;
+
+
+/// Cells the LCS table may have, about 16 MB; larger changes are diffed coarsely.
+const MAX_TABLE_CELLS: usize = 4_000_000;
+
+/// One line of a line-based diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change<'a> {
+    Same(&'a str),
+    Added(&'a str),
+    Removed(&'a str),
+}
+
+/// Formats the line prefixed with ` `, `+` or `-` as in a unified diff.
+impl<'a> fmt::Display for Change<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::Same(line) => write!(f, " {}", line),
+            Change::Added(line) => write!(f, "+{}", line),
+            Change::Removed(line) => write!(f, "-{}", line),
+        }
+    }
+}
+
+
+/// Diffs `old` against `new` line by line, keeping a longest common
+/// subsequence of lines; removals come before additions where both apply.
+///
+/// When the changed lines between the common prefix and suffix are too many
+/// for the table, they are shown as all removed and then all added.
+pub fn lines<'a>(old: &'a str, new: &'a str) -> Vec<Change<'a>> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // Only the middle between a common prefix and suffix needs the table.
+    let prefix = a.iter().zip(b.iter()).take_while(|&(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|&(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let (n, m) = (a_mid.len(), b_mid.len());
+    let mut changes: Vec<Change> = a[..prefix].iter().map(|l| Change::Same(*l)).collect();
+    if (n + 1).saturating_mul(m + 1) > MAX_TABLE_CELLS {
+        changes.extend(a_mid.iter().map(|l| Change::Removed(*l)));
+        changes.extend(b_mid.iter().map(|l| Change::Added(*l)));
+        changes.extend(a[a.len() - suffix..].iter().map(|l| Change::Same(*l)));
+        return changes;
+    }
+
+    // lcs[i][j]: common lines of a_mid[i..] and b_mid[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if a_mid[i] == b_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && a_mid[i] == b_mid[j] {
+            changes.push(Change::Same(a_mid[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            changes.push(Change::Removed(a_mid[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(b_mid[j]));
+            j += 1;
+        }
+    }
+    changes.extend(a[a.len() - suffix..].iter().map(|l| Change::Same(*l)));
+    changes
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        assert!(lines("a\nb\nc", "a\nb\nc").iter().all(|c| match *c { Change::Same(_) => true, _ => false }));
+        assert!(lines("", "a") == vec![Change::Added("a")]);
+        assert!(lines("a", "") == vec![Change::Removed("a")]);
+
+        let changes = lines("title\none\ntwo\nthree\nend", "title\none\n2\nthree\nfour\nend");
+        assert!(changes == vec![Change::Same("title"), Change::Same("one"), Change::Removed("two"), Change::Added("2"),
+                                Change::Same("three"), Change::Added("four"), Change::Same("end")],
+                "changes: {:?}", changes);
+        assert!(changes.iter().map(|c| c.to_string()).collect::<Vec<_>>()
+                == vec![" title", " one", "-two", "+2", " three", "+four", " end"]);
+
+        let old: String = (0..2100).map(|i| format!("old {}\n", i)).collect();
+        let new: String = (0..2100).map(|i| format!("new {}\n", i)).collect();
+        let changes = lines(&format!("top\n{}end", old), &format!("top\n{}end", new));
+        assert!(changes.len() == 4202 && changes[0] == Change::Same("top") && changes[4201] == Change::Same("end"));
+        assert!(changes[1] == Change::Removed("old 0") && changes[2101] == Change::Added("new 0"));
+    }
+}
@@ -0,0 +1,38 @@
+use chrono::NaiveDateTime;
+use rocket_contrib::{JSON, Value};
+use models::ApiKey;
+use auth::{self, Admin, SCOPES};
+use db::{DB, api_key};
+use handlers::errors::{ApiError, ApiResult};
+
+
+#[get("/apikey")]
+pub fn get_all(db: DB, _admin: Admin) -> ApiResult<JSON<Vec<ApiKey>>> {
+    Ok(JSON(api_key::get(db.conn(), None)?))
+}
+
+
+#[derive(Deserialize)]
+pub struct ApiKeyInput {
+    name: String,
+    scopes: Vec<String>,
+    expires: Option<NaiveDateTime>,
+}
+
+#[post("/apikey/create", format="application/json", data="<key>")]
+pub fn create(db: DB, _admin: Admin, key: JSON<ApiKeyInput>) -> ApiResult<JSON<Value>> { // returns id and key
+    if let Some(s) = key.scopes.iter().find(|s| !SCOPES.contains(&s.as_str())) {
+        return Err(ApiError::bad_request(format!("unknown scope: {}", s)));
+    }
+
+    let secret = auth::generate_api_key();
+    let created = api_key::create(db.conn(), &key.name, &auth::hash_token(&secret), &key.scopes, key.expires)?;
+    Ok(JSON(json!({ "status": "ok", "id": created.id, "key": secret })))
+}
+
+
+#[delete("/apikey/<id>")]
+pub fn delete(db: DB, _admin: Admin, id: i32) -> ApiResult<JSON<Value>> {
+    api_key::delete(db.conn(), id)?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
@@ -0,0 +1,44 @@
+use rocket::http::{Cookie, Cookies, Status};
+use rocket_contrib::{JSON, Value};
+use auth::{self, User, SESSION_COOKIE};
+use db::{DB, user, Error};
+use handlers::errors::{ApiError, ApiResult};
+
+
+#[derive(Deserialize)]
+pub struct Credentials {
+    name: String,
+    password: String,
+}
+
+#[post("/login", format="application/json", data="<credentials>")]
+pub fn login(db: DB, cookies: &Cookies, credentials: JSON<Credentials>) -> ApiResult<JSON<Value>> { // returns token
+    let invalid = || ApiError::new(Status::Unauthorized, "invalid_credentials", "invalid credentials");
+    let account = match user::find_by_name(db.conn(), &credentials.name) {
+        Ok(u) => u,
+        Err(Error::RecordNotFound) => {
+            auth::reject_password(&credentials.password);
+            return Err(invalid());
+        },
+        Err(e) => return Err(e.into()),
+    };
+    if !auth::verify_password(&credentials.password, &account.password) {
+        return Err(invalid());
+    }
+
+    let token = auth::generate_token();
+    user::create_session(db.conn(), account.id, &auth::hash_token(&token), auth::session_expiry())?;
+    cookies.add(Cookie::build(SESSION_COOKIE, token.clone())
+                .path("/")
+                .http_only(true)
+                .finish());
+    Ok(JSON(json!({ "status": "ok", "token": token, "role": account.role })))
+}
+
+
+#[post("/logout")]
+pub fn logout(db: DB, cookies: &Cookies, current: User) -> ApiResult<JSON<Value>> {
+    cookies.remove(SESSION_COOKIE);
+    user::delete_session(db.conn(), current.session())?;
+    Ok(JSON(json!({ "status": "ok" })))
+}
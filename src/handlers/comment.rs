@@ -1,53 +1,452 @@
+use std::collections::HashMap;
+
+use chrono::prelude::*;
+use rocket::http::Cookies;
 use rocket_contrib::{JSON, Value};
-use models::{Comment, NewComment};
-use db::{DB, comment, Error};
+use models::{Comment, NewVisitor};
+use auth::{Admin, Auth, CommentModerate};
+use spam::{self, Blacklist, Submission};
+use notify;
+use throttle::{Throttle, CommentCreate};
+use db::{self, DB, comment, post, subscription, visitor, Error};
+use db::page::Page;
+use handlers::errors::{ApiError, ApiResult};
+use handlers::params;
+use handlers::version::{IfMatch, Tagged};
+use handlers::visitor::{PublicVisitor, VisitorToken, check_mail, issue_token, mail_taken, send_verification};
+
 
 
+#[derive(FromForm, Default)]
+pub struct CommentQuery {
+    post: Option<i32>,
+    visitor: Option<i32>,
+    since: Option<String>,
+    until: Option<String>,
+    sort: Option<String>,
+    after: Option<String>,
+    before: Option<String>,
+    limit: Option<i64>,
+    status: Option<String>,
+}
+
+
+fn parse_status(status: &str) -> ApiResult<comment::Status> {
+    comment::Status::parse(status).ok_or(ApiError::bad_request(format!("invalid status: {}", status)))
+}
+
 
-#[get("/comment")]
-pub fn get_all(db: DB) -> JSON<Vec<Comment>> {
-    JSON(comment::get(db.conn(), None, false))
+#[get("/comment", rank = 2)]
+pub fn get_all(db: DB, moderator: Option<Auth<CommentModerate>>) -> ApiResult<JSON<Page<Comment>>> {
+    get_page(db, moderator, CommentQuery::default())
 }
 
 
+/// Lists comments; only moderators may ask for other statuses than `approved`,
+/// see spam scores and comments on posts that are not live.
+#[get("/comment?<query>")]
+pub fn get_page(db: DB, moderator: Option<Auth<CommentModerate>>, query: CommentQuery) -> ApiResult<JSON<Page<Comment>>> {
+    let status = match query.status.as_ref().map(|s| s.as_str()) {
+        None | Some("approved") => Some(comment::Status::Approved),
+        Some(_) if moderator.is_none() => return Err(ApiError::forbidden()),
+        Some("all") => None,
+        Some(s) => Some(parse_status(s)?),
+    };
+    let filter = comment::Filter {
+        pid: query.post,
+        vid: query.visitor,
+        since: params::date("since", &query.since)?,
+        until: params::date("until", &query.until)?,
+        status: status,
+        live_posts: moderator.is_none(),
+    };
+    let page = params::page(&query.sort, &query.after, &query.before, query.limit)?;
+    let page = comment::list(db.conn(), &filter, &page)?;
+    if moderator.is_some() {
+        return Ok(JSON(page));
+    }
+    Ok(JSON(page.map(|mut c| { c.spam_score = None; c })))
+}
+
+
+#[derive(FromForm, Default)]
+pub struct PostCommentQuery {
+    sort: Option<String>,
+    after: Option<String>,
+    before: Option<String>,
+    limit: Option<i64>,
+}
+
+
+#[derive(Serialize)]
+pub struct PostComment {
+    id: i32,
+    parent_id: Option<i32>,
+    body: String,
+    created: NaiveDateTime,
+    last_edited: NaiveDateTime,
+    visitor: Option<PublicVisitor>,
+}
+
+
+#[get("/post/<id>/comments", rank = 2)]
+pub fn get_for_post(db: DB, id: i32) -> ApiResult<JSON<Page<PostComment>>> {
+    get_page_for_post(db, id, PostCommentQuery::default())
+}
+
+
+/// Lists a published post's approved comments, oldest first unless `sort=newest`.
+#[get("/post/<id>/comments?<query>")]
+pub fn get_page_for_post(db: DB, id: i32, query: PostCommentQuery) -> ApiResult<JSON<Page<PostComment>>> {
+    if post::get_published(db.conn(), Some(id))?.is_empty() {
+        return Err(ApiError::not_found());
+    }
+
+    let filter = comment::Filter {
+        pid: Some(id),
+        status: Some(comment::Status::Approved),
+        ..comment::Filter::default()
+    };
+    let sort = query.sort.clone().or(Some("oldest".into()));
+    let page = params::page(&sort, &query.after, &query.before, query.limit)?;
+    let comments = comment::list(db.conn(), &filter, &page)?;
+
+    let vids: Vec<i32> = comments.items.iter().map(|c| c.vid).collect();
+    let visitors: HashMap<i32, PublicVisitor> = visitor::get_many(db.conn(), &vids)?
+        .into_iter()
+        .map(|v| (v.id, PublicVisitor::from(v)))
+        .collect();
+
+    Ok(JSON(comments.map(|c| PostComment {
+        id: c.id,
+        parent_id: c.parent_id,
+        visitor: visitors.get(&c.vid).cloned(),
+        body: c.body,
+        created: c.created,
+        last_edited: c.last_edited,
+    })))
+}
+
+
+const DEFAULT_DEPTH: usize = 5;
+const MAX_DEPTH: usize = 20;
+
+#[derive(FromForm, Default)]
+pub struct ThreadQuery {
+    max_depth: Option<usize>,
+}
+
+
+/// A comment in a reply tree; comments no longer approved keep their place as "[deleted]".
+#[derive(Serialize)]
+pub struct ThreadComment {
+    id: i32,
+    deleted: bool,
+    body: String,
+    created: NaiveDateTime,
+    last_edited: NaiveDateTime,
+    visitor: Option<PublicVisitor>,
+    replies: Vec<ThreadComment>,
+}
+
+fn collect_vids(threads: &[comment::Thread], vids: &mut Vec<i32>) {
+    for t in threads {
+        vids.push(t.comment.vid);
+        collect_vids(&t.replies, vids);
+    }
+}
+
+fn thread_view(threads: Vec<comment::Thread>, visitors: &HashMap<i32, PublicVisitor>) -> Vec<ThreadComment> {
+    threads.into_iter().map(|t| {
+        let c = t.comment;
+        let hidden = c.status != comment::Status::Approved.as_str();
+        ThreadComment {
+            id: c.id,
+            deleted: hidden,
+            body: if hidden { "[deleted]".into() } else { c.body },
+            created: c.created,
+            last_edited: c.last_edited,
+            visitor: if hidden { None } else { visitors.get(&c.vid).cloned() },
+            replies: thread_view(t.replies, visitors),
+        }
+    }).collect()
+}
+
+
+#[get("/post/<id>/comments/tree", rank = 2)]
+pub fn get_tree(db: DB, id: i32) -> ApiResult<JSON<Vec<ThreadComment>>> {
+    get_tree_with(db, id, ThreadQuery::default())
+}
+
+
+/// Lists a published post's comments as nested replies.
+#[get("/post/<id>/comments/tree?<query>")]
+pub fn get_tree_with(db: DB, id: i32, query: ThreadQuery) -> ApiResult<JSON<Vec<ThreadComment>>> {
+    let max_depth = match query.max_depth.unwrap_or(DEFAULT_DEPTH) {
+        0 => return Err(ApiError::bad_request("max_depth must be at least 1")),
+        d if d > MAX_DEPTH => MAX_DEPTH,
+        d => d,
+    };
+    if post::get_published(db.conn(), Some(id))?.is_empty() {
+        return Err(ApiError::not_found());
+    }
+
+    let threads = comment::thread(db.conn(), id, max_depth)?;
+    let mut vids = Vec::new();
+    collect_vids(&threads, &mut vids);
+    let visitors: HashMap<i32, PublicVisitor> = visitor::get_many(db.conn(), &vids)?
+        .into_iter()
+        .map(|v| (v.id, PublicVisitor::from(v)))
+        .collect();
+
+    Ok(JSON(thread_view(threads, &visitors)))
+}
+
+
+/// Sends the comment with its version as `ETag`, for `If-Match` on updates;
+/// moderators get comments that are not approved, or on posts that are not
+/// live, as well.
 #[get("/comment/<id>")]
-pub fn get(db: DB, id: i32) -> Option<JSON<Comment>> {
-    let mut comment = comment::get(db.conn(), Some(id), true);
-    comment.pop().map(|v| JSON(v))
+pub fn get(db: DB, moderator: Option<Auth<CommentModerate>>, id: i32) -> ApiResult<Tagged<JSON<Comment>>> {
+    let mut comment = comment::get(db.conn(), Some(id), moderator.is_none())?.pop().ok_or(ApiError::not_found())?;
+    if moderator.is_none() {
+        if post::get_published(db.conn(), Some(comment.pid))?.is_empty() {
+            return Err(ApiError::not_found());
+        }
+        comment.spam_score = None;
+    }
+    let version = db::version(comment.last_edited);
+    Ok(Tagged(JSON(comment), version))
 }
 
 
-#[post("/comment/create", format="application/json", data="<comment>")]
-pub fn create(db: DB, comment: JSON<NewComment>) -> JSON<Value> { // returns id
-    let comment = comment::create(db.conn(), comment.pid, comment.vid, &comment.body);
-    match comment {
-        Ok(p) => JSON(json!({ "status": "ok", "id": p.id })),
-        Err(Error::ForeignKeyViolation) =>
-            JSON(json!({ "status": "error", "description": "Invalid post id or visitor id" })),
-        _ => JSON(json!({ "status": "error", "description": "database error" }))
+fn invalid_reference(e: Error) -> ApiError {
+    match e {
+        Error::ForeignKeyViolation => ApiError::from(e).describe("Invalid post, visitor or parent id"),
+        _ => ApiError::from(e)
     }
 }
 
 
-#[post("/comment/<id>", format="application/json", data="<comment>")]
-pub fn update(db: DB, id: i32, comment: JSON<NewComment>) -> JSON<Value> { // returns id
-    let comment = comment::update(db.conn(), id, &comment.body);
-    match comment {
-        Ok(p) => JSON(json!({ "status": "ok", "id": p.id })),
-        Err(Error::RecordNotFound) => JSON(json!({ "status": "error", "description": "not found" })),
-        Err(Error::ForeignKeyViolation) =>
-            JSON(json!({ "status": "error", "description": "Invalid post id or visitor id" })),
-        _ => JSON(json!({ "status": "error", "description": "database error" }))
+/// A new comment by either `vid`, a visitor the client holds a token for, or
+/// `visitor`, looked up by address: a new address creates the visitor, one
+/// that belongs to a visitor the client holds no token for is refused.
+#[derive(Deserialize)]
+pub struct CommentInput {
+    pid: i32,
+    vid: Option<i32>,
+    visitor: Option<NewVisitor>,
+    body: String,
+    parent_id: Option<i32>,
+    /// Hidden form field; anything in it marks the comment as spam.
+    honeypot: Option<String>,
+    /// Unix time at which the comment form was shown.
+    rendered_at: Option<i64>,
+    /// Whether to mail the author about later comments on the post; only
+    /// verified addresses are mailed, so a new visitor confirms it through
+    /// the verification link.
+    subscribe: Option<bool>,
+}
+
+/// Creates a comment on a live post; it waits in the moderation queue unless
+/// the site policy lets it through, and goes straight to spam if it scores
+/// high enough.
+///
+/// Subscribers hear about it once it is approved; moderators are alerted when
+/// it waits in the queue. A new visitor gets a visitor token back.
+#[post("/comment/create", format="application/json", data="<input>")]
+pub fn create(db: DB, cookies: &Cookies, throttle: Throttle<CommentCreate>, owner: VisitorToken,
+              input: JSON<CommentInput>) -> ApiResult<JSON<Value>> { // returns id
+    throttle.check()?;
+    if post::get_published(db.conn(), Some(input.pid))?.is_empty() {
+        return Err(ApiError::not_found().describe("no such post"));
+    }
+    let mut token = None;
+    let author = match (input.vid, input.visitor.as_ref()) {
+        (Some(vid), None) => {
+            let author = visitor::get(db.conn(), Some(vid), true)?.pop()
+                .ok_or(invalid_reference(Error::ForeignKeyViolation))?;
+            if !owner.owns(&author) {
+                return Err(ApiError::forbidden().describe("visitor token required"));
+            }
+            author
+        },
+        (None, Some(v)) => {
+            check_mail(&v.mail)?;
+            match visitor::find_by_mail(db.conn(), &v.mail) {
+                Ok(author) => if owner.owns(&author) {
+                    author
+                } else {
+                    return Err(mail_taken(Error::UniqueViolation));
+                },
+                Err(Error::RecordNotFound) => {
+                    let author = visitor::create(db.conn(), &v.name, &v.mail, v.site.clone())
+                        .map_err(mail_taken)?;
+                    send_verification(db.conn(), &author);
+                    token = Some(issue_token(cookies, &author));
+                    author
+                },
+                Err(e) => return Err(e.into()),
+            }
+        },
+        _ => return Err(ApiError::bad_request("give either vid or visitor")),
+    };
+    let vid = author.id;
+    throttle.check_visitor(vid)?;
+
+    let submission = Submission {
+        body: &input.body,
+        honeypot: input.honeypot.as_ref().map(|h| h.as_str()),
+        elapsed: input.rendered_at.map(|t| UTC::now().timestamp() - t),
+    };
+    let score = spam::score(db.conn(), &submission)?;
+    // The client proved it owns `author` above, so their verification counts.
+    let verified = author.verified_at.is_some();
+    let status = spam::route(score, comment::initial_status(db.conn(), &author)?, verified);
+    let comment = comment::create(db.conn(), input.pid, vid, &input.body, input.parent_id, status, Some(score))
+        .map_err(invalid_reference)?;
+    // Only for a visitor the client owns; checked above.
+    if input.subscribe == Some(true) {
+        subscription::subscribe(db.conn(), vid, input.pid)?;
+    }
+    match status {
+        comment::Status::Approved => notify::approved(db.conn(), &[comment.id]),
+        comment::Status::Pending => notify::pending(db.conn(), &comment, &author),
+        _ => {},
     }
+    Ok(JSON(json!({ "status": "ok", "id": comment.id, "vid": vid, "moderation": comment.status, "token": token })))
+}
+
+
+#[derive(Deserialize)]
+pub struct CommentUpdate {
+    body: String,
+}
+
+/// Updates a comment; with `If-Match` only the version it names.
+#[post("/comment/<id>", format="application/json", data="<input>")]
+pub fn update(db: DB, _auth: Auth<CommentModerate>, if_match: IfMatch, id: i32,
+              input: JSON<CommentUpdate>) -> ApiResult<Tagged<JSON<Value>>> { // returns id
+    let comment = comment::update(db.conn(), id, &input.body, if_match.0)?;
+    let version = db::version(comment.last_edited);
+    Ok(Tagged(JSON(json!({ "status": "ok", "id": comment.id, "version": version })), version))
 }
 
 
 #[delete("/comment/<id>")]
-pub fn delete(db: DB, id: i32) -> JSON<Value> {
-    let num = comment::delete(db.conn(), id);
-    match num {
-        Ok(_) => JSON(json!({ "status": "ok", "id": id })),
-        Err(Error::RecordNotFound) => JSON(json!({ "status": "error", "description": "not found" })),
-        _ => JSON(json!({ "status": "error", "description": "database error" }))
+pub fn delete(db: DB, _auth: Auth<CommentModerate>, if_match: IfMatch, id: i32) -> ApiResult<JSON<Value>> {
+    comment::delete(db.conn(), id, if_match.0)?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+#[derive(FromForm, Default)]
+pub struct QueueQuery {
+    post: Option<i32>,
+    sort: Option<String>,
+    after: Option<String>,
+    before: Option<String>,
+    limit: Option<i64>,
+}
+
+
+#[get("/comment/queue", rank = 2)]
+pub fn get_queue(db: DB, auth: Auth<CommentModerate>) -> ApiResult<JSON<Page<Comment>>> {
+    get_queue_page(db, auth, QueueQuery::default())
+}
+
+
+/// Lists comments awaiting moderation, oldest first unless `sort=newest`.
+#[get("/comment/queue?<query>")]
+pub fn get_queue_page(db: DB, _auth: Auth<CommentModerate>, query: QueueQuery) -> ApiResult<JSON<Page<Comment>>> {
+    let filter = comment::Filter {
+        pid: query.post,
+        status: Some(comment::Status::Pending),
+        ..comment::Filter::default()
+    };
+    let sort = query.sort.clone().or(Some("oldest".into()));
+    let page = params::page(&sort, &query.after, &query.before, query.limit)?;
+    Ok(JSON(comment::list(db.conn(), &filter, &page)?))
+}
+
+
+#[post("/comment/<id>/approve")]
+pub fn approve(db: DB, _auth: Auth<CommentModerate>, id: i32) -> ApiResult<JSON<Value>> {
+    spam::moderate(db.conn(), &[id], comment::Status::Approved)?;
+    notify::approved(db.conn(), &[id]);
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+#[post("/comment/<id>/reject")]
+pub fn reject(db: DB, _auth: Auth<CommentModerate>, id: i32) -> ApiResult<JSON<Value>> {
+    spam::moderate(db.conn(), &[id], comment::Status::Rejected)?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+#[post("/comment/<id>/spam")]
+pub fn mark_spam(db: DB, _auth: Auth<CommentModerate>, id: i32) -> ApiResult<JSON<Value>> {
+    spam::moderate(db.conn(), &[id], comment::Status::Spam)?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+#[derive(Deserialize)]
+pub struct ModerateInput {
+    ids: Vec<i32>,
+    status: String,
+}
+
+/// Moves many comments to `approved`, `rejected`, `spam` or back to `pending` at once.
+#[post("/comment/moderate", format="application/json", data="<input>")]
+pub fn moderate(db: DB, _auth: Auth<CommentModerate>, input: JSON<ModerateInput>) -> ApiResult<JSON<Value>> { // returns count
+    let status = match parse_status(&input.status)? {
+        comment::Status::Deleted => return Err(ApiError::bad_request("use DELETE /comment/<id> to delete")),
+        s => s,
+    };
+    if input.ids.is_empty() {
+        return Err(ApiError::bad_request("no comment ids given"));
     }
+    let num = spam::moderate(db.conn(), &input.ids, status)?;
+    if status == comment::Status::Approved {
+        notify::approved(db.conn(), &input.ids);
+    }
+    Ok(JSON(json!({ "status": "ok", "count": num })))
+}
+
+
+#[get("/comment/policy")]
+pub fn get_policy(db: DB, _admin: Admin) -> ApiResult<JSON<Value>> {
+    let policy = comment::policy(db.conn())?;
+    Ok(JSON(json!({ "status": "ok", "policy": policy.as_str() })))
+}
+
+
+#[derive(Deserialize)]
+pub struct PolicyInput {
+    policy: String,
+}
+
+/// Sets which new comments are moderated: `always`, `first_time` or `never`.
+#[post("/comment/policy", format="application/json", data="<input>")]
+pub fn set_policy(db: DB, _admin: Admin, input: JSON<PolicyInput>) -> ApiResult<JSON<Value>> {
+    let policy = comment::Policy::parse(&input.policy)
+        .ok_or(ApiError::bad_request(format!("invalid policy: {}", input.policy)))?;
+    comment::set_policy(db.conn(), policy)?;
+    Ok(JSON(json!({ "status": "ok", "policy": policy.as_str() })))
+}
+
+
+#[get("/comment/spam/blacklist")]
+pub fn get_blacklist(db: DB, _admin: Admin) -> ApiResult<JSON<Blacklist>> {
+    Ok(JSON(spam::blacklist(db.conn())?))
+}
+
+
+/// Replaces the words and link domains that count against a comment.
+#[post("/comment/spam/blacklist", format="application/json", data="<input>")]
+pub fn set_blacklist(db: DB, _admin: Admin, input: JSON<Blacklist>) -> ApiResult<JSON<Value>> {
+    spam::set_blacklist(db.conn(), &input)?;
+    Ok(JSON(json!({ "status": "ok" })))
 }
@@ -1,8 +1,160 @@
-use rocket::response::content;
+use rocket::http::Status;
+use rocket::response::{self, Responder};
+use rocket::response::status;
 use rocket::Request;
+use rocket_contrib::{JSON, Value};
+use db;
+use throttle::Limited;
+use handlers::version::etag;
+
+
+/// Error returned by API handlers; responds with `status` and a JSON body of
+/// the form `{ "status": "error", "code": ..., "description": ... }`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    pub code: &'static str,
+    pub description: String,
+    /// Seconds to send as `Retry-After`.
+    pub retry_after: Option<u64>,
+    /// Current version of the record, sent as `ETag` and in the body.
+    pub version: Option<i64>,
+    /// Underlying failure, logged when the error is sent but never shown.
+    pub cause: Option<String>,
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+
+fn error_body(code: &str, description: &str) -> Value {
+    json!({ "status": "error", "code": code, "description": description })
+}
+
+
+impl ApiError {
+    pub fn new<S: Into<String>>(status: Status, code: &'static str, description: S) -> ApiError {
+        ApiError { status: status, code: code, description: description.into(), retry_after: None, version: None, cause: None }
+    }
+
+    pub fn bad_request<S: Into<String>>(description: S) -> ApiError {
+        ApiError::new(Status::BadRequest, "bad_request", description)
+    }
+
+    pub fn forbidden() -> ApiError {
+        ApiError::new(Status::Forbidden, "forbidden", "permission denied")
+    }
+
+    pub fn not_found() -> ApiError {
+        ApiError::new(Status::NotFound, "not_found", "not found")
+    }
+
+    /// Replaces the description, keeping status and code.
+    pub fn describe<S: Into<String>>(mut self, description: S) -> ApiError {
+        self.description = description.into();
+        self
+    }
+}
+
+
+impl From<db::Error> for ApiError {
+    fn from(e: db::Error) -> ApiError {
+        match e {
+            db::Error::RecordNotFound => ApiError::not_found(),
+            db::Error::ForeignKeyViolation =>
+                ApiError::new(Status::UnprocessableEntity, "invalid_reference", "referenced record does not exist"),
+            db::Error::UniqueViolation =>
+                ApiError::new(Status::Conflict, "conflict", "record already exists"),
+            db::Error::InvalidTransition(from, to) =>
+                ApiError::new(Status::Conflict, "invalid_transition", format!("cannot move from {} to {}", from, to)),
+            db::Error::MissingParameter(name) => ApiError::bad_request(format!("missing {}", name)),
+            db::Error::VersionMismatch(current) => {
+                let mut err = ApiError::new(Status::PreconditionFailed, "version_mismatch",
+                                            "the record was changed by someone else");
+                err.version = Some(current);
+                err
+            },
+            db::Error::UnableToSendCommand(cause) => {
+                let mut err = ApiError::new(Status::ServiceUnavailable, "unavailable", "database unavailable");
+                err.cause = Some(format!("Database unavailable: {}", cause));
+                err
+            },
+            db::Error::DatabaseError(cause) => {
+                let mut err = ApiError::new(Status::InternalServerError, "database_error", "database error");
+                err.cause = Some(format!("Database error: {}", cause));
+                err
+            },
+        }
+    }
+}
+
+
+impl From<Limited> for ApiError {
+    fn from(e: Limited) -> ApiError {
+        let mut err = ApiError::new(Status::TooManyRequests, "rate_limited",
+                                    format!("too many requests, retry in {} seconds", e.retry_after));
+        err.retry_after = Some(e.retry_after);
+        err
+    }
+}
+
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond(self) -> response::Result<'r> {
+        if let Some(ref cause) = self.cause {
+            println!("    => {}", cause);
+        }
+        let mut body = error_body(self.code, &self.description);
+        if let Some(version) = self.version {
+            body["version"] = json!(version);
+        }
+        let mut response = status::Custom(self.status, JSON(body)).respond()?;
+        if let Some(secs) = self.retry_after {
+            response.set_raw_header("Retry-After", secs.to_string());
+        }
+        if let Some(version) = self.version {
+            response.set_raw_header("ETag", etag(version));
+        }
+        Ok(response)
+    }
+}
+
+
+#[error(400)]
+fn bad_request(_: &Request) -> JSON<Value> {
+    JSON(error_body("bad_request", "malformed request"))
+}
+
+#[error(401)]
+fn unauthorized(_: &Request) -> JSON<Value> {
+    JSON(error_body("unauthorized", "authentication required"))
+}
+
+#[error(403)]
+fn forbidden(_: &Request) -> JSON<Value> {
+    JSON(error_body("forbidden", "permission denied"))
+}
 
 #[error(404)]
-fn not_found(req: &Request) -> content::HTML<String> {
-    content::HTML(format!("<p>Page {} not found<p>", req.uri()))
+fn not_found(req: &Request) -> JSON<Value> {
+    JSON(error_body("not_found", &format!("{} not found", req.uri())))
 }
 
+#[error(422)]
+fn unprocessable_entity(_: &Request) -> JSON<Value> {
+    JSON(error_body("unprocessable_entity", "request body could not be parsed"))
+}
+
+#[error(429)]
+fn too_many_requests(_: &Request) -> JSON<Value> {
+    JSON(error_body("rate_limited", "too many requests"))
+}
+
+#[error(500)]
+fn internal_error(_: &Request) -> JSON<Value> {
+    JSON(error_body("internal_error", "internal server error"))
+}
+
+#[error(503)]
+fn service_unavailable(_: &Request) -> JSON<Value> {
+    JSON(error_body("unavailable", "service unavailable"))
+}
@@ -1,5 +1,15 @@
 pub mod errors;
+pub mod params;
+pub mod auth;
+pub mod api_key;
+pub mod user;
 pub mod post;
 pub mod visitor;
 pub mod comment;
 
+pub mod subscription;
+pub mod trash;
+pub mod scheduler;
+pub mod revision;
+pub mod tag;
+pub mod version;
@@ -0,0 +1,34 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use db::page::{Cursor, PageRequest, Position, Sort, DEFAULT_LIMIT};
+use handlers::errors::{ApiError, ApiResult};
+
+
+/// Builds a `PageRequest` from the `sort`, `after`, `before` and `limit` parameters.
+pub fn page(sort: &Option<String>, after: &Option<String>, before: &Option<String>,
+            limit: Option<i64>) -> ApiResult<PageRequest> {
+    let sort = match *sort {
+        Some(ref s) => Sort::parse(s).ok_or(ApiError::bad_request(format!("invalid sort: {}", s)))?,
+        None => Sort::Newest,
+    };
+    let cursor = |c: &str| Cursor::parse(c).map_err(|e| ApiError::bad_request(format!("invalid cursor {}: {}", c, e)));
+    let position = match (after, before) {
+        (&Some(_), &Some(_)) => return Err(ApiError::bad_request("after and before are exclusive")),
+        (&Some(ref c), &None) => Position::After(cursor(c)?),
+        (&None, &Some(ref c)) => Position::Before(cursor(c)?),
+        (&None, &None) => Position::First,
+    };
+    Ok(PageRequest::new(sort, position, limit.unwrap_or(DEFAULT_LIMIT)))
+}
+
+
+/// Parses a `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS` parameter as UTC.
+pub fn date(name: &str, value: &Option<String>) -> ApiResult<Option<NaiveDateTime>> {
+    let value = match *value {
+        Some(ref v) => v,
+        None => return Ok(None),
+    };
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|d| d.and_hms(0, 0, 0)))
+        .map(Some)
+        .map_err(|_| ApiError::bad_request(format!("invalid {}: {}", name, value)))
+}
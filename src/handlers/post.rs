@@ -1,19 +1,96 @@
+use chrono::prelude::*;
 use rocket_contrib::{ JSON, Value };
-use models::Post;
-use db::{DB, post, Error};
+use models::{Post, PostView, PostTransition};
+use auth::{Auth, Scope, PostPublish, PostWrite};
+use db::{self, DB, Error, post, tag};
+use db::page::Page;
+use handlers::errors::{ApiError, ApiResult};
+use handlers::params;
+use handlers::version::{IfMatch, Tagged};
 
 
 
-#[get("/post")]
-pub fn get_all(db: DB) -> JSON<Vec<Post>> {
-    JSON(post::get_published(db.conn(), None))
+#[derive(FromForm, Default)]
+pub struct PostQuery {
+    category: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    published: Option<String>,
+    state: Option<String>,
+    sort: Option<String>,
+    after: Option<String>,
+    before: Option<String>,
+    limit: Option<i64>,
 }
 
 
+#[get("/post", rank = 2)]
+pub fn get_all(db: DB, writer: Option<Auth<PostWrite>>,
+               publisher: Option<Auth<PostPublish>>) -> ApiResult<JSON<Page<PostView>>> {
+    get_page(db, writer, publisher, PostQuery::default())
+}
+
+
+/// Lists posts, optionally those with the tag named by `category`; only
+/// writers may ask for posts that are not live with `published=false|all` or
+/// `state`, and users who may not publish only see their own. An API key
+/// owns no posts, so it needs `post:publish` for that.
+#[get("/post?<query>")]
+pub fn get_page(db: DB, writer: Option<Auth<PostWrite>>, publisher: Option<Auth<PostPublish>>,
+                query: PostQuery) -> ApiResult<JSON<Page<PostView>>> {
+    let writer = match writer {
+        Some(ref w) if w.user_id().is_none() && publisher.is_none() => None,
+        w => w,
+    };
+    let published = match query.published.as_ref().map(|s| s.as_str()) {
+        None | Some("true") => Some(true),
+        Some(_) if writer.is_none() => return Err(ApiError::forbidden()),
+        Some("false") => Some(false),
+        Some("all") => None,
+        Some(p) => return Err(ApiError::bad_request(format!("invalid published: {}", p))),
+    };
+    let state = match query.state {
+        Some(_) if writer.is_none() => return Err(ApiError::forbidden()),
+        Some(ref s) => Some(post::State::parse(s).ok_or(ApiError::bad_request(format!("invalid state: {}", s)))?),
+        None => None,
+    };
+    let filter = post::Filter {
+        tag: query.category.as_ref().map(|c| tag::slugify(c)),
+        since: params::date("since", &query.since)?,
+        until: params::date("until", &query.until)?,
+        published: if state.is_some() && query.published.is_none() { None } else { published },
+        state: state,
+        author: if publisher.is_none() { writer.as_ref().and_then(|w| w.user_id()) } else { None },
+    };
+    let page = params::page(&query.sort, &query.after, &query.before, query.limit)?;
+    let posts = post::list(db.conn(), &filter, &page)?;
+    Ok(JSON(tag::view_page(db.conn(), posts)?))
+}
+
+
+/// Whether `writer` may see `post` while it is not live, or its history:
+/// those who may publish see every post, other users only their own as in
+/// the listing, and API keys none.
+pub fn can_see_unpublished(post: &Post, writer: &Auth<PostWrite>, publisher: &Option<Auth<PostPublish>>) -> bool {
+    publisher.is_some() || writer.user_id().map_or(false, |uid| post.uid == Some(uid))
+}
+
+
+/// Sends the post with its version as `ETag`, for `If-Match` on updates.
+/// Writers also get posts that are not live, limited to their own as in the
+/// listing unless they may publish; API keys need `post:publish` for them.
 #[get("/post/<id>")]
-pub fn get(db: DB, id: i32) -> Option<JSON<Post>> {
-    let mut posts = post::get_published(db.conn(), Some(id));
-    posts.pop().map(|p| JSON(p))
+pub fn get(db: DB, writer: Option<Auth<PostWrite>>, publisher: Option<Auth<PostPublish>>,
+           id: i32) -> ApiResult<Tagged<JSON<PostView>>> {
+    let post = post::get(db.conn(), Some(id), false, true)?.pop().ok_or(ApiError::not_found())?;
+    let visible = post::is_live(&post, UTC::now().naive_utc())
+        || writer.as_ref().map_or(false, |w| can_see_unpublished(&post, w, &publisher));
+    if !visible {
+        return Err(ApiError::not_found());
+    }
+    let version = db::version(post.last_edited);
+    let categories = tag::for_post(db.conn(), post.id)?;
+    Ok(Tagged(JSON(PostView::new(post, categories)), version))
 }
 
 
@@ -24,54 +101,125 @@ pub struct PostInput {
     body: String,
 }
 
+/// Loads a post the caller is about to modify, checking ownership.
+fn editable<S: Scope>(db: &DB, auth: &Auth<S>, id: i32) -> ApiResult<Post> {
+    match post::get(db.conn(), Some(id), false, true)?.pop() {
+        Some(ref p) if !auth.can_edit(p) => Err(ApiError::forbidden()),
+        Some(p) => Ok(p),
+        None => Err(ApiError::not_found()),
+    }
+}
+
+
 #[post("/post/create", format="application/json", data="<post>")]
-pub fn create(db: DB, post: JSON<PostInput>) -> JSON<Value> { // returns id
+pub fn create(db: DB, auth: Auth<PostWrite>, post: JSON<PostInput>) -> ApiResult<JSON<Value>> { // returns id
     let cats = if post.categories.len() > 0 {
         Some(&post.categories)
     } else {
         None
     };
-    let post = post::create(db.conn(), &post.title, cats, &post.body);
-    match post {
-        Ok(p) => JSON(json!({ "status": "ok", "id": p.id })),
-        _ => JSON(json!({ "status": "database error" }))
-    }
+    let post = post::create(db.conn(), &post.title, cats, &post.body, auth.user_id())?;
+    Ok(JSON(json!({ "status": "ok", "id": post.id })))
 }
 
 
+/// Updates a post; with `If-Match` only the version it names.
 #[post("/post/<id>", format="application/json", data="<post>")]
-pub fn update(db: DB, id: i32, post: JSON<PostInput>) -> JSON<Value> { // returns id
+pub fn update(db: DB, auth: Auth<PostWrite>, if_match: IfMatch, id: i32,
+              post: JSON<PostInput>) -> ApiResult<Tagged<JSON<Value>>> { // returns id
+    editable(&db, &auth, id)?;
     let cats = if post.categories.len() > 0 {
         Some(&post.categories)
     } else {
         None
     };
-    let post = post::update(db.conn(), id, &post.title, cats, &post.body);
-    match post {
-        Ok(p) => JSON(json!({ "status": "ok", "id": p.id })),
-        Err(Error::RecordNotFound) => JSON(json!({ "status": "error", "description": "not found" })),
-        _ => JSON(json!({ "status": "error", "description": "database error" }))
-    }
+    let post = post::update(db.conn(), id, &post.title, cats, &post.body, auth.user_id(), if_match.0)?;
+    let version = db::version(post.last_edited);
+    Ok(Tagged(JSON(json!({ "status": "ok", "id": post.id, "version": version })), version))
 }
 
 
 #[post("/post/<id>/publish")]
-pub fn publish(db: DB, id: i32) -> JSON<Value> {
-    let post = post::publish(db.conn(), id);
-    match post {
-        Ok(_) => JSON(json!({ "status": "ok", "id": id })),
-        Err(Error::RecordNotFound) => JSON(json!({ "status": "error", "description": "not found" })),
-        _ => JSON(json!({ "status": "error", "description": "database error" }))
+pub fn publish(db: DB, auth: Auth<PostPublish>, id: i32) -> ApiResult<JSON<Value>> {
+    post::publish(db.conn(), id, auth.user_id())?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+#[post("/post/<id>/unpublish")]
+pub fn unpublish(db: DB, auth: Auth<PostPublish>, id: i32) -> ApiResult<JSON<Value>> {
+    post::transition(db.conn(), id, post::State::Unpublished, auth.user_id())?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+#[derive(Deserialize)]
+pub struct ScheduleInput {
+    /// UTC, `YYYY-MM-DDTHH:MM:SS`.
+    publish_at: String,
+}
+
+/// Schedules a post to go live at a future time, or reschedules it.
+#[post("/post/<id>/schedule", format="application/json", data="<input>")]
+pub fn schedule(db: DB, auth: Auth<PostPublish>, id: i32, input: JSON<ScheduleInput>) -> ApiResult<JSON<Value>> {
+    let at = params::date("publish_at", &Some(input.publish_at.clone()))?.unwrap();
+    if at <= UTC::now().naive_utc() {
+        return Err(ApiError::bad_request("publish_at must be in the future"));
     }
+    post::schedule(db.conn(), id, at, auth.user_id())?;
+    Ok(JSON(json!({ "status": "ok", "id": id, "publish_at": at })))
 }
 
 
-#[delete("/post/<id>")]
-pub fn delete(db: DB, id: i32) -> JSON<Value> {
-    let num = post::delete(db.conn(), id);
-    match num {
-        Ok(_) => JSON(json!({ "status": "ok", "id": id })),
-        Err(Error::RecordNotFound) => JSON(json!({ "status": "error", "description": "not found" })),
-        _ => JSON(json!({ "status": "error", "description": "database error" }))
+/// Cancels a scheduled publication, leaving the post a draft; a post whose
+/// time has come is live and has to be unpublished instead.
+#[delete("/post/<id>/schedule")]
+pub fn unschedule(db: DB, auth: Auth<PostPublish>, id: i32) -> ApiResult<JSON<Value>> {
+    post::unschedule(db.conn(), id, auth.user_id()).map_err(|e| match e {
+        Error::InvalidTransition("published", _) =>
+            ApiError::from(e).describe("post is already live; unpublish it instead"),
+        Error::InvalidTransition(..) => ApiError::from(e).describe("post is not scheduled"),
+        _ => ApiError::from(e),
+    })?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+#[derive(Deserialize)]
+pub struct StateInput {
+    state: String,
+}
+
+/// Moves a post to another editorial state. Without `post:publish` a writer
+/// may only move their own post between draft and review.
+#[post("/post/<id>/state", format="application/json", data="<input>")]
+pub fn set_state(db: DB, writer: Auth<PostWrite>, publisher: Option<Auth<PostPublish>>,
+                 id: i32, input: JSON<StateInput>) -> ApiResult<JSON<Value>> {
+    let state = match post::State::parse(&input.state) {
+        Some(post::State::Scheduled) => return Err(ApiError::bad_request("use /post/<id>/schedule to schedule a post")),
+        Some(state) => state,
+        None => return Err(ApiError::bad_request(format!("invalid state: {}", input.state))),
+    };
+    if publisher.is_none() {
+        editable(&db, &writer, id)?;
+        if state != post::State::Draft && state != post::State::Review {
+            return Err(ApiError::forbidden());
+        }
     }
+    let post = post::transition(db.conn(), id, state, writer.user_id())?;
+    Ok(JSON(json!({ "status": "ok", "id": id, "state": post.state })))
+}
+
+
+#[get("/post/<id>/transitions")]
+pub fn transitions(db: DB, _auth: Auth<PostWrite>, id: i32) -> ApiResult<JSON<Vec<PostTransition>>> {
+    Ok(JSON(post::transitions(db.conn(), id)?))
+}
+
+
+#[delete("/post/<id>")]
+pub fn delete(db: DB, auth: Auth<PostWrite>, if_match: IfMatch, id: i32) -> ApiResult<JSON<Value>> {
+    editable(&db, &auth, id)?;
+    post::delete(db.conn(), id, if_match.0)?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
 }
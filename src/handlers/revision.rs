@@ -0,0 +1,71 @@
+use rocket_contrib::{JSON, Value};
+use models::PostRevision;
+use auth::{Auth, PostPublish, PostWrite};
+use db::{DB, post, revision};
+use diff;
+use handlers::errors::{ApiError, ApiResult};
+use handlers::post::can_see_unpublished;
+
+
+/// Checks that the caller may read the history of post `id`; as for a post
+/// that is not live, anyone else gets 404.
+fn check_readable(db: &DB, writer: &Auth<PostWrite>, publisher: &Option<Auth<PostPublish>>, id: i32) -> ApiResult<()> {
+    match post::get(db.conn(), Some(id), false, true)?.pop() {
+        Some(ref p) if can_see_unpublished(p, writer, publisher) => Ok(()),
+        _ => Err(ApiError::not_found()),
+    }
+}
+
+
+#[get("/post/<id>/revisions")]
+pub fn get_all(db: DB, writer: Auth<PostWrite>, publisher: Option<Auth<PostPublish>>,
+               id: i32) -> ApiResult<JSON<Vec<PostRevision>>> {
+    check_readable(&db, &writer, &publisher, id)?;
+    Ok(JSON(revision::for_post(db.conn(), id)?))
+}
+
+
+#[get("/post/<id>/revisions/<rev>")]
+pub fn get(db: DB, writer: Auth<PostWrite>, publisher: Option<Auth<PostPublish>>,
+           id: i32, rev: i32) -> ApiResult<JSON<PostRevision>> {
+    check_readable(&db, &writer, &publisher, id)?;
+    Ok(JSON(revision::get(db.conn(), id, rev)?))
+}
+
+
+#[derive(FromForm)]
+pub struct DiffQuery {
+    from: i32,
+    to: i32,
+}
+
+/// Line-based diff of the title, categories and body of two revisions; each
+/// line starts with ` `, `+` or `-`.
+#[get("/post/<id>/diff?<query>")]
+pub fn get_diff(db: DB, writer: Auth<PostWrite>, publisher: Option<Auth<PostPublish>>,
+                id: i32, query: DiffQuery) -> ApiResult<JSON<Value>> {
+    check_readable(&db, &writer, &publisher, id)?;
+    let old = revision::get(db.conn(), id, query.from)?;
+    let new = revision::get(db.conn(), id, query.to)?;
+    let lines = |a: &str, b: &str| diff::lines(a, b).iter().map(|c| c.to_string()).collect::<Vec<_>>();
+    Ok(JSON(json!({
+        "from": old.rev,
+        "to": new.rev,
+        "title": lines(&old.title, &new.title),
+        "categories": lines(&old.categories.join("\n"), &new.categories.join("\n")),
+        "body": lines(&old.body, &new.body),
+    })))
+}
+
+
+/// Makes an old revision the current content, saved as a new revision.
+#[post("/post/<id>/revisions/<rev>/restore")]
+pub fn restore(db: DB, auth: Auth<PostWrite>, id: i32, rev: i32) -> ApiResult<JSON<Value>> { // returns new revision
+    match post::get(db.conn(), Some(id), false, true)?.pop() {
+        Some(ref p) if !auth.can_edit(p) => return Err(ApiError::forbidden()),
+        Some(_) => (),
+        None => return Err(ApiError::not_found()),
+    }
+    let revision = post::restore_revision(db.conn(), id, rev, auth.user_id())?;
+    Ok(JSON(json!({ "status": "ok", "id": id, "rev": revision.rev })))
+}
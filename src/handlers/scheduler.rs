@@ -0,0 +1,12 @@
+use rocket_contrib::{JSON, Value};
+use auth::Admin;
+use db::DB;
+use scheduler;
+use handlers::errors::ApiResult;
+
+
+/// Lists the maintenance tasks with their schedules and last recorded run.
+#[get("/scheduler")]
+pub fn status(db: DB, _admin: Admin) -> ApiResult<JSON<Vec<Value>>> {
+    Ok(JSON(scheduler::status(db.conn())?))
+}
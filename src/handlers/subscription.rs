@@ -0,0 +1,40 @@
+use rocket::http::Status;
+use rocket::response::content::HTML;
+use rocket_contrib::{JSON, Value};
+use notify::{UNSUBSCRIBE_PURPOSE, unsubscribe_binding};
+use token::{self, TokenError};
+use db::{DB, subscription, Error};
+use handlers::errors::{ApiError, ApiResult};
+
+
+/// Visitor an unsubscribe link for post `pid` was sent to.
+fn subscriber(pid: i32, token: &str) -> ApiResult<i32> {
+    match token::verify(UNSUBSCRIBE_PURPOSE, token, &unsubscribe_binding(pid)) {
+        Ok(vid) => Ok(vid),
+        Err(TokenError::Expired) => Err(ApiError::new(Status::BadRequest, "expired_token", "unsubscribe link expired")),
+        Err(TokenError::Invalid) => Err(ApiError::new(Status::BadRequest, "invalid_token", "invalid unsubscribe link")),
+    }
+}
+
+
+/// The link in a notification only asks for confirmation, so that mail
+/// scanners opening it do not unsubscribe anyone.
+#[get("/post/<pid>/unsubscribe/<token>")]
+pub fn confirm_unsubscribe(pid: i32, token: String) -> ApiResult<HTML<String>> {
+    subscriber(pid, &token)?;
+    Ok(HTML(format!("<!DOCTYPE html>\n<title>Unsubscribe</title>\n\
+                     <form method=\"post\"><p>Stop getting mail about new comments on post {}?</p>\
+                     <button type=\"submit\">Unsubscribe</button></form>\n", pid)))
+}
+
+
+/// Unsubscribes the visitor a notification was sent to; succeeds even if
+/// they already unsubscribed, so the form can be sent twice.
+#[post("/post/<pid>/unsubscribe/<token>")]
+pub fn unsubscribe(db: DB, pid: i32, token: String) -> ApiResult<JSON<Value>> {
+    let vid = subscriber(pid, &token)?;
+    match subscription::unsubscribe(db.conn(), vid, pid) {
+        Ok(_) | Err(Error::RecordNotFound) => Ok(JSON(json!({ "status": "ok", "id": pid }))),
+        Err(e) => Err(ApiError::from(e)),
+    }
+}
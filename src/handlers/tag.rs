@@ -0,0 +1,79 @@
+use rocket_contrib::{JSON, Value};
+use models::PostView;
+use auth::{Auth, PostPublish};
+use db::{DB, post, tag, Error};
+use db::page::Page;
+use handlers::errors::{ApiError, ApiResult};
+use handlers::params;
+
+
+
+/// Lists tags by name with the number of live posts carrying each.
+#[get("/tag")]
+pub fn get_all(db: DB) -> ApiResult<JSON<Vec<tag::TagCount>>> {
+    Ok(JSON(tag::all(db.conn())?))
+}
+
+
+#[derive(FromForm, Default)]
+pub struct TagPostQuery {
+    sort: Option<String>,
+    after: Option<String>,
+    before: Option<String>,
+    limit: Option<i64>,
+}
+
+
+#[get("/tag/<slug>/posts", rank = 2)]
+pub fn get_posts(db: DB, slug: String) -> ApiResult<JSON<Page<PostView>>> {
+    get_page_posts(db, slug, TagPostQuery::default())
+}
+
+
+/// Lists the live posts with a tag; `total` is the tag's post count.
+#[get("/tag/<slug>/posts?<query>")]
+pub fn get_page_posts(db: DB, slug: String, query: TagPostQuery) -> ApiResult<JSON<Page<PostView>>> {
+    tag::get(db.conn(), &slug)?;
+    let filter = post::Filter {
+        tag: Some(slug),
+        published: Some(true),
+        ..post::Filter::default()
+    };
+    let page = params::page(&query.sort, &query.after, &query.before, query.limit)?;
+    let posts = post::list(db.conn(), &filter, &page)?;
+    Ok(JSON(tag::view_page(db.conn(), posts)?))
+}
+
+
+#[derive(Deserialize)]
+pub struct TagRename {
+    name: String,
+}
+
+/// Renames a tag on every post carrying it, which makes a new revision of
+/// each; its slug follows the new name.
+#[post("/tag/<slug>", format="application/json", data="<input>")]
+pub fn rename(db: DB, auth: Auth<PostPublish>, slug: String, input: JSON<TagRename>) -> ApiResult<JSON<Value>> {
+    if tag::slugify(&input.name).is_empty() {
+        return Err(ApiError::bad_request("invalid name"));
+    }
+    let tag = tag::rename(db.conn(), &slug, &input.name, auth.user_id()).map_err(|e| match e {
+        Error::UniqueViolation => ApiError::from(e).describe("name taken; merge the tags instead"),
+        _ => ApiError::from(e)
+    })?;
+    Ok(JSON(json!({ "status": "ok", "id": tag.id, "slug": tag.slug })))
+}
+
+
+#[derive(Deserialize)]
+pub struct TagMerge {
+    into: String,
+}
+
+/// Moves the posts of a tag to the tag with slug `into`, making a new
+/// revision of each, and removes it.
+#[post("/tag/<slug>/merge", format="application/json", data="<input>")]
+pub fn merge(db: DB, auth: Auth<PostPublish>, slug: String, input: JSON<TagMerge>) -> ApiResult<JSON<Value>> {
+    let tag = tag::merge(db.conn(), &slug, &input.into, auth.user_id())?;
+    Ok(JSON(json!({ "status": "ok", "id": tag.id, "slug": tag.slug })))
+}
@@ -0,0 +1,69 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use rocket_contrib::{JSON, Value};
+use models::{Comment, PostView};
+use auth::Admin;
+use db::{DB, comment, post, tag};
+use handlers::errors::{ApiError, ApiResult};
+
+
+#[get("/trash/post")]
+pub fn get_posts(db: DB, _admin: Admin) -> ApiResult<JSON<Vec<PostView>>> {
+    let posts = post::trash(db.conn())?;
+    Ok(JSON(tag::view(db.conn(), posts)?))
+}
+
+
+#[get("/trash/comment")]
+pub fn get_comments(db: DB, _admin: Admin) -> ApiResult<JSON<Vec<Comment>>> {
+    Ok(JSON(comment::trash(db.conn())?))
+}
+
+
+#[post("/trash/post/<id>/restore")]
+pub fn restore_post(db: DB, _admin: Admin, id: i32) -> ApiResult<JSON<Value>> {
+    post::restore(db.conn(), id)?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+/// Restores a comment with the status it had when it was deleted.
+#[post("/trash/comment/<id>/restore")]
+pub fn restore_comment(db: DB, _admin: Admin, id: i32) -> ApiResult<JSON<Value>> {
+    let comment = comment::restore(db.conn(), id)?;
+    Ok(JSON(json!({ "status": "ok", "id": id, "moderation": comment.status })))
+}
+
+
+/// Purges a deleted post and every comment on it.
+#[delete("/trash/post/<id>")]
+pub fn purge_post(db: DB, _admin: Admin, id: i32) -> ApiResult<JSON<Value>> {
+    post::purge_one(db.conn(), id)?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+#[delete("/trash/comment/<id>")]
+pub fn purge_comment(db: DB, _admin: Admin, id: i32) -> ApiResult<JSON<Value>> {
+    comment::purge_one(db.conn(), id)?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+#[derive(FromForm)]
+pub struct PurgeQuery {
+    /// Only purge items deleted at least this many days ago.
+    older_than: i64,
+}
+
+/// Purges posts and comments that have been in the trash for a while.
+#[delete("/trash?<query>")]
+pub fn purge(db: DB, _admin: Admin, query: PurgeQuery) -> ApiResult<JSON<Value>> { // returns counts
+    if query.older_than < 0 {
+        return Err(ApiError::bad_request("older_than must not be negative"));
+    }
+    let before = UTC::now().naive_utc() - Duration::days(query.older_than);
+    let comments = comment::purge_before(db.conn(), before)?;
+    let posts = post::purge_before(db.conn(), before)?;
+    Ok(JSON(json!({ "status": "ok", "posts": posts, "comments": comments })))
+}
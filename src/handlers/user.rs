@@ -0,0 +1,64 @@
+use rocket_contrib::{JSON, Value};
+use models::User;
+use auth::{self, Admin, Role};
+use db::{DB, user, Error};
+use handlers::errors::{ApiError, ApiResult};
+
+
+#[get("/user")]
+pub fn get_all(db: DB, _admin: Admin) -> ApiResult<JSON<Vec<User>>> {
+    Ok(JSON(user::get(db.conn(), None)?))
+}
+
+
+fn parse_role(role: &str) -> ApiResult<Role> {
+    Role::parse(role).ok_or(ApiError::bad_request(format!("unknown role: {}", role)))
+}
+
+
+#[derive(Deserialize)]
+pub struct UserInput {
+    name: String,
+    password: String,
+    role: String,
+}
+
+#[post("/user/create", format="application/json", data="<input>")]
+pub fn create(db: DB, _admin: Admin, input: JSON<UserInput>) -> ApiResult<JSON<Value>> { // returns id
+    let role = parse_role(&input.role)?;
+    let created = user::create(db.conn(), &input.name, &auth::hash_password(&input.password), role.as_str())
+        .map_err(|e| match e {
+            Error::UniqueViolation => ApiError::from(e).describe("name taken"),
+            _ => ApiError::from(e)
+        })?;
+    Ok(JSON(json!({ "status": "ok", "id": created.id })))
+}
+
+
+#[derive(Deserialize)]
+pub struct UserUpdate {
+    password: Option<String>,
+    role: Option<String>,
+}
+
+/// Changes a user's role or password; either ends their sessions.
+#[post("/user/<id>", format="application/json", data="<input>")]
+pub fn update(db: DB, _admin: Admin, id: i32, input: JSON<UserUpdate>) -> ApiResult<JSON<Value>> { // returns id
+    if let Some(ref role) = input.role {
+        user::set_role(db.conn(), id, parse_role(role)?.as_str())?;
+    }
+    if let Some(ref password) = input.password {
+        user::set_password(db.conn(), id, &auth::hash_password(password))?;
+    }
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+#[delete("/user/<id>")]
+pub fn delete(db: DB, admin: Admin, id: i32) -> ApiResult<JSON<Value>> {
+    if admin.0.id == id {
+        return Err(ApiError::bad_request("cannot delete yourself"));
+    }
+    user::delete(db.conn(), id)?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
@@ -0,0 +1,53 @@
+use rocket::request::{Outcome, FromRequest};
+use rocket::response::{self, Responder};
+use rocket::Outcome::{Success, Failure};
+use rocket::http::Status;
+use rocket::Request;
+
+
+/// Formats a row version as an `ETag` value.
+pub fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+
+/// Sends `R` with the row version it represents as its `ETag`.
+pub struct Tagged<R>(pub R, pub i64);
+
+impl<'r, R: Responder<'r>> Responder<'r> for Tagged<R> {
+    fn respond(self) -> response::Result<'r> {
+        let mut response = self.0.respond()?;
+        response.set_raw_header("ETag", etag(self.1));
+        Ok(response)
+    }
+}
+
+
+/// The version named by an `If-Match` header, as sent back from an `ETag`
+/// and possibly marked weak by a proxy; `None` if the header is absent or
+/// `*`. Anything else is a bad request.
+pub struct IfMatch(pub Option<i64>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let value = match request.headers().get_one("If-Match") {
+            Some(value) => value.trim(),
+            None => return Success(IfMatch(None)),
+        };
+        if value == "*" {
+            return Success(IfMatch(None));
+        }
+        let value = if value.starts_with("W/") { &value[2..] } else { value };
+        let version = if value.len() > 2 && value.starts_with('"') && value.ends_with('"') {
+            value[1..value.len() - 1].parse::<i64>().ok()
+        } else {
+            None
+        };
+        match version {
+            Some(v) => Success(IfMatch(Some(v))),
+            None => Failure((Status::BadRequest, ())),
+        }
+    }
+}
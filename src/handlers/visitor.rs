@@ -1,48 +1,314 @@
+// Hashing
+use crypto::digest::Digest;
+use crypto::md5::Md5;
+
+use diesel::pg::PgConnection;
+
+use chrono::{Duration, NaiveDateTime};
+use rocket::request::{Outcome, FromRequest};
+use rocket::Outcome::Success;
+use rocket::Request;
+use rocket::http::{Cookie, Cookies, Status};
 use rocket_contrib::{JSON, Value};
-use models::{Visitor, NewVisitor};
+use models::{AuditEntry, Visitor, NewVisitor};
+use auth::{Admin, Auth, VisitorRead, VisitorWrite};
+use mail::{self, SITE_URL};
+use mail::template::VERIFY_VISITOR;
+use throttle::{Throttle, VisitorCreate};
+use token::{self, TokenError};
+use privacy::{self, CommentErasure, Export};
 use db::{DB, visitor, Error};
+use db::page::Page;
+use handlers::errors::{ApiError, ApiResult};
+use handlers::params;
+
+
+/// What anyone may see of a visitor; never includes the email address.
+#[derive(Serialize, Clone)]
+pub struct PublicVisitor {
+    pub id: i32,
+    pub name: String,
+    pub site: Option<String>,
+    /// MD5 of the trimmed, lowercased address, as Gravatar expects.
+    pub avatar: String,
+    pub created: NaiveDateTime,
+    pub verified: bool,
+}
+
+impl From<Visitor> for PublicVisitor {
+    /// Deleted visitors show up as "deleted user", without their details.
+    fn from(v: Visitor) -> PublicVisitor {
+        if v.deleted {
+            return PublicVisitor {
+                id: v.id,
+                name: "deleted user".into(),
+                site: None,
+                avatar: String::new(),
+                created: v.created,
+                verified: false,
+            };
+        }
+        let mut hasher = Md5::new();
+        hasher.input_str(&v.mail.trim().to_lowercase());
+        PublicVisitor {
+            id: v.id,
+            name: v.name,
+            site: v.site,
+            avatar: hasher.result_str(),
+            created: v.created,
+            verified: v.verified_at.is_some(),
+        }
+    }
+}
 
 
-#[get("/visitor")]
-pub fn get_all(db: DB) -> JSON<Vec<Visitor>> {
-    JSON(visitor::get(db.conn(), None))
+#[derive(FromForm, Default)]
+pub struct VisitorQuery {
+    since: Option<String>,
+    until: Option<String>,
+    sort: Option<String>,
+    after: Option<String>,
+    before: Option<String>,
+    limit: Option<i64>,
 }
 
 
+#[get("/visitor", rank = 2)]
+pub fn get_all(db: DB, auth: Auth<VisitorRead>) -> ApiResult<JSON<Page<Visitor>>> {
+    get_page(db, auth, VisitorQuery::default())
+}
+
+
+/// Lists visitors with their email addresses; needs `visitor:read`.
+#[get("/visitor?<query>")]
+pub fn get_page(db: DB, _auth: Auth<VisitorRead>, query: VisitorQuery) -> ApiResult<JSON<Page<Visitor>>> {
+    let filter = visitor::Filter {
+        since: params::date("since", &query.since)?,
+        until: params::date("until", &query.until)?,
+    };
+    let page = params::page(&query.sort, &query.after, &query.before, query.limit)?;
+    Ok(JSON(visitor::list(db.conn(), &filter, &page)?))
+}
+
+
+/// Shows a visitor; only callers with `visitor:read` see the email address.
 #[get("/visitor/<id>")]
-pub fn get(db: DB, id: i32) -> Option<JSON<Visitor>> {
-    let mut visitors = visitor::get(db.conn(), Some(id));
-    visitors.pop().map(|v| JSON(v))
+pub fn get(db: DB, reader: Option<Auth<VisitorRead>>, id: i32) -> ApiResult<JSON<Value>> {
+    let v = visitor::get(db.conn(), Some(id), true)?.pop().ok_or(ApiError::not_found())?;
+    if reader.is_some() {
+        Ok(JSON(json!(v)))
+    } else {
+        Ok(JSON(json!(PublicVisitor::from(v))))
+    }
 }
 
 
-#[post("/visitor/create", format="application/json", data="<visitor>")]
-pub fn create(db: DB, visitor: JSON<NewVisitor>) -> JSON<Value> { // returns id
-    let visitor = visitor::create(db.conn(), &visitor.name, &visitor.mail, visitor.site.clone());
-    match visitor {
-        Ok(p) => JSON(json!({ "status": "ok", "id": p.id })),
-        _ => JSON(json!({ "status": "database error" }))
+pub fn mail_taken(e: Error) -> ApiError {
+    match e {
+        Error::UniqueViolation => ApiError::from(e).describe("mail taken"),
+        _ => ApiError::from(e)
     }
 }
 
 
-#[post("/visitor/<id>", format="application/json", data="<visitor>")]
-pub fn update(db: DB, id: i32, visitor: JSON<NewVisitor>) -> JSON<Value> { // returns id
-    let visitor = visitor::update(db.conn(), id, &visitor.name, &visitor.mail, visitor.site.clone());
-    match visitor {
-        Ok(p) => JSON(json!({ "status": "ok", "id": p.id })),
-        Err(Error::RecordNotFound) => JSON(json!({ "status": "error", "description": "not found" })),
-        _ => JSON(json!({ "status": "error", "description": "database error" }))
+const VERIFY_PURPOSE: &'static str = "verify_visitor";
+const VERIFY_DAYS: i64 = 2;
+
+fn mail_binding(v: &Visitor) -> String {
+    v.mail.trim().to_lowercase()
+}
+
+
+pub const VISITOR_COOKIE: &'static str = "visitor";
+const VISITOR_HEADER: &'static str = "X-Visitor-Token";
+const VISITOR_PURPOSE: &'static str = "visitor";
+const VISITOR_TOKEN_DAYS: i64 = 365;
+
+/// What a visitor token is bound to: the address and when it was verified,
+/// so a token handed to whoever created the visitor stops working once the
+/// owner of the address verifies it.
+fn visitor_binding(v: &Visitor) -> String {
+    let verified = v.verified_at.map_or("unverified".to_string(), |t| t.timestamp().to_string());
+    format!("{}\n{}", mail_binding(v), verified)
+}
+
+
+/// Gives the client a token proving it owns visitor `v`, as a cookie and as
+/// the returned string for clients that send it in `X-Visitor-Token`.
+pub fn issue_token(cookies: &Cookies, v: &Visitor) -> String {
+    let token = token::sign(VISITOR_PURPOSE, v.id, &visitor_binding(v), Duration::days(VISITOR_TOKEN_DAYS));
+    cookies.add(Cookie::build(VISITOR_COOKIE, token.clone())
+                .path("/")
+                .http_only(true)
+                .finish());
+    token
+}
+
+
+/// Request guard for the visitor token a client holds, if any.
+pub struct VisitorToken(Option<String>);
+
+impl VisitorToken {
+    /// Whether the token proves the client owns `v`.
+    pub fn owns(&self, v: &Visitor) -> bool {
+        match self.0 {
+            Some(ref t) => !v.deleted && token::verify(VISITOR_PURPOSE, t, &visitor_binding(v)) == Ok(v.id),
+            None => false,
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for VisitorToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let token = request.headers().get_one(VISITOR_HEADER).map(|t| t.trim().to_string())
+            .or(request.cookies().find(VISITOR_COOKIE).map(|c| c.value().to_string()));
+        Success(VisitorToken(token))
+    }
+}
+
+
+/// Queues mail with a link that verifies a visitor's address; failures are
+/// only logged since the visitor can ask for another one.
+pub fn send_verification(conn: &PgConnection, v: &Visitor) {
+    let token = token::sign(VERIFY_PURPOSE, v.id, &mail_binding(v), Duration::days(VERIFY_DAYS));
+    let link = format!("{}/visitor/verify/{}", *SITE_URL, token);
+    let days = VERIFY_DAYS.to_string();
+    let message = VERIFY_VISITOR.render(&v.mail, &[("name", v.name.as_str()),
+                                                    ("days", days.as_str()),
+                                                    ("link", link.as_str())]);
+    let result = match message {
+        Ok(message) => mail::queue(conn, &message).map(|_| ()).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = result {
+        println!("    => Failed to queue verification for visitor {}: {}", v.id, e);
     }
 }
 
 
-#[delete("/visitor/<id>")]
-pub fn delete(db: DB, id: i32) -> JSON<Value> {
-    let num = visitor::delete(db.conn(), id);
-    match num {
-        Ok(_) => JSON(json!({ "status": "ok", "id": id })),
-        Err(Error::RecordNotFound) => JSON(json!({ "status": "error", "description": "not found" })),
-        _ => JSON(json!({ "status": "error", "description": "database error" }))
+/// Refuses an address that cannot be mailed safely.
+pub fn check_mail(mail: &str) -> ApiResult<()> {
+    if mail::valid_address(mail.trim()) {
+        Ok(())
+    } else {
+        Err(ApiError::bad_request("invalid mail"))
     }
 }
+
+
+/// Creates a visitor and hands back a visitor token for commenting as them.
+#[post("/visitor/create", format="application/json", data="<visitor>")]
+pub fn create(db: DB, cookies: &Cookies, throttle: Throttle<VisitorCreate>,
+              visitor: JSON<NewVisitor>) -> ApiResult<JSON<Value>> { // returns id
+    throttle.check()?;
+    check_mail(&visitor.mail)?;
+    let visitor = visitor::create(db.conn(), &visitor.name, &visitor.mail, visitor.site.clone())
+        .map_err(mail_taken)?;
+    send_verification(db.conn(), &visitor);
+    let token = issue_token(cookies, &visitor);
+    Ok(JSON(json!({ "status": "ok", "id": visitor.id, "token": token })))
+}
+
+
+/// Sends another verification link to an unverified visitor; limited per
+/// visitor as well, since anyone may ask.
+#[post("/visitor/<id>/verify")]
+pub fn resend_verification(db: DB, throttle: Throttle<VisitorCreate>, id: i32) -> ApiResult<JSON<Value>> {
+    throttle.check()?;
+    throttle.check_mail(id)?;
+    let v = visitor::get(db.conn(), Some(id), true)?.pop().ok_or(ApiError::not_found())?;
+    if v.verified_at.is_some() {
+        return Err(ApiError::bad_request("already verified"));
+    }
+    send_verification(db.conn(), &v);
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+/// Verifies a visitor's address and hands back a visitor token, the only
+/// one that carries the verified status.
+#[get("/visitor/verify/<token>")]
+pub fn verify(db: DB, cookies: &Cookies, token: String) -> ApiResult<JSON<Value>> {
+    let invalid = || ApiError::new(Status::BadRequest, "invalid_token", "invalid verification link");
+    let id = token::peek(&token).ok_or(invalid())?;
+    let v = visitor::get(db.conn(), Some(id), true)?.pop().ok_or(invalid())?;
+    match token::verify(VERIFY_PURPOSE, &token, &mail_binding(&v)) {
+        Ok(_) => {
+            let v = visitor::verify(db.conn(), id)?;
+            let token = issue_token(cookies, &v);
+            Ok(JSON(json!({ "status": "ok", "id": id, "token": token })))
+        },
+        Err(TokenError::Expired) => Err(ApiError::new(Status::BadRequest, "expired_token", "verification link expired")),
+        Err(TokenError::Invalid) => Err(invalid()),
+    }
+}
+
+
+#[post("/visitor/<id>", format="application/json", data="<visitor>")]
+pub fn update(db: DB, _auth: Auth<VisitorWrite>, id: i32, visitor: JSON<NewVisitor>) -> ApiResult<JSON<Value>> { // returns id
+    check_mail(&visitor.mail)?;
+    let visitor = visitor::update(db.conn(), id, &visitor.name, &visitor.mail, visitor.site.clone())
+        .map_err(mail_taken)?;
+    Ok(JSON(json!({ "status": "ok", "id": visitor.id })))
+}
+
+
+#[derive(FromForm, Default)]
+pub struct DeleteQuery {
+    /// `keep`, `delete` or `refuse` (default)
+    comments: Option<String>,
+}
+
+
+#[delete("/visitor/<id>", rank = 2)]
+pub fn delete(db: DB, auth: Auth<VisitorWrite>, id: i32) -> ApiResult<JSON<Value>> {
+    delete_with(db, auth, id, DeleteQuery::default())
+}
+
+
+/// Deletes a visitor; `comments` says whether their comments are kept as by
+/// a deleted user, deleted too, or stop the visitor from being deleted.
+#[delete("/visitor/<id>?<query>")]
+pub fn delete_with(db: DB, _auth: Auth<VisitorWrite>, id: i32, query: DeleteQuery) -> ApiResult<JSON<Value>> {
+    let cascade = match query.comments {
+        Some(ref c) => visitor::Cascade::parse(c).ok_or(ApiError::bad_request(format!("invalid comments option: {}", c)))?,
+        None => visitor::Cascade::Refuse,
+    };
+    visitor::delete(db.conn(), id, cascade).map_err(|e| match e {
+        Error::ForeignKeyViolation => ApiError::from(e).describe("visitor has comments; pass comments=keep or comments=delete"),
+        _ => ApiError::from(e)
+    })?;
+    Ok(JSON(json!({ "status": "ok", "id": id })))
+}
+
+
+/// Everything stored about a visitor, for data access requests; admins only.
+#[get("/visitor/<id>/export", rank = 2)]
+pub fn export(db: DB, admin: Admin, id: i32) -> ApiResult<JSON<Export>> {
+    Ok(JSON(privacy::export(db.conn(), id, Some(admin.0.id))?))
+}
+
+
+#[derive(Deserialize)]
+pub struct EraseInput {
+    /// `anonymize` or `remove`
+    comments: String,
+}
+
+/// Erases a visitor's personal data for erasure requests; admins only.
+#[post("/visitor/<id>/erase", format="application/json", data="<input>")]
+pub fn erase(db: DB, admin: Admin, id: i32, input: JSON<EraseInput>) -> ApiResult<JSON<Value>> { // returns id
+    let comments = CommentErasure::parse(&input.comments)
+        .ok_or(ApiError::bad_request(format!("invalid comments option: {}", input.comments)))?;
+    let num = privacy::erase(db.conn(), id, comments, Some(admin.0.id))?;
+    Ok(JSON(json!({ "status": "ok", "id": id, "comments": num })))
+}
+
+
+/// Exports and erasures of a visitor, newest first; admins only.
+#[get("/visitor/<id>/audit", rank = 2)]
+pub fn audit(db: DB, _admin: Admin, id: i32) -> ApiResult<JSON<Vec<AuditEntry>>> {
+    Ok(JSON(privacy::history(db.conn(), id)?))
+}
@@ -0,0 +1,72 @@
+// Timestamp
+use chrono::prelude::*;
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use mail::{Message, MailError, Transport};
+
+
+static DELIVERIES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+
+/// Delivers into a maildir at `dir`, so that any mail client can read what
+/// would have been sent; meant for development and tests.
+pub struct MaildirTransport {
+    pub dir: PathBuf,
+}
+
+impl MaildirTransport {
+    /// Creates the `tmp`, `new` and `cur` folders if needed.
+    fn prepare(&self) -> Result<(), MailError> {
+        for sub in &["tmp", "new", "cur"] {
+            fs::create_dir_all(self.dir.join(sub)).map_err(|e| MailError(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Transport for MaildirTransport {
+    /// Writes the message to `tmp` and moves it to `new` once complete, so
+    /// readers never see half a message.
+    fn send(&self, message: &Message) -> Result<(), MailError> {
+        self.prepare()?;
+        let now = UTC::now();
+        let name = format!("{}.M{}Q{}.planetmeow", now.timestamp(), now.timestamp_subsec_nanos(),
+                           DELIVERIES.fetch_add(1, Ordering::SeqCst));
+        let tmp = self.dir.join("tmp").join(&name);
+        File::create(&tmp)
+            .and_then(|mut file| file.write_all(message.to_string().as_bytes()))
+            .and_then(|_| fs::rename(&tmp, self.dir.join("new").join(&name)))
+            .map_err(|e| MailError(e.to_string()))
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::io::Read;
+
+    #[test]
+    fn test_maildir() {
+        let dir = env::temp_dir().join("planetmeow-maildir-test");
+        let _ = fs::remove_dir_all(&dir);
+        let transport = MaildirTransport { dir: dir.clone() };
+        let message = Message { to: "a@b.c".into(), subject: "hi".into(), body: "hello".into() };
+        transport.send(&message).unwrap();
+        transport.send(&message).unwrap();
+
+        assert!(fs::read_dir(dir.join("tmp")).unwrap().count() == 0);
+        let files: Vec<_> = fs::read_dir(dir.join("new")).unwrap().map(|e| e.unwrap().path()).collect();
+        assert!(files.len() == 2);
+        let mut content = String::new();
+        File::open(&files[0]).unwrap().read_to_string(&mut content).unwrap();
+        assert!(content == "To: a@b.c\nSubject: hi\n\nhello\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
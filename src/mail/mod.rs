@@ -0,0 +1,179 @@
+// DB ORM
+use diesel::pg::PgConnection;
+
+// Timestamp
+use chrono::prelude::*;
+use chrono::Duration;
+
+use std::fmt;
+
+use config;
+use models::OutboxMail;
+use db::{DBResult, outbox};
+
+
+pub mod template;
+pub mod maildir;
+pub mod smtp;
+
+
+/// Mail sent per `deliver_due` run.
+const BATCH_SIZE: i64 = 20;
+/// How long a claimed message is held back from other instances while it is sent.
+const CLAIM_MINUTES: i64 = 10;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "To: {}\nSubject: {}\n\n{}\n", self.to, self.subject, self.body)
+    }
+}
+
+impl From<OutboxMail> for Message {
+    fn from(mail: OutboxMail) -> Message {
+        Message {
+            to: mail.recipient,
+            subject: mail.subject,
+            body: mail.body,
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct MailError(pub String);
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+
+/// Delivers outgoing mail.
+pub trait Transport: Send + Sync {
+    fn send(&self, message: &Message) -> Result<(), MailError>;
+}
+
+
+/// Prints messages to stdout instead of sending them.
+pub struct LogTransport;
+
+impl Transport for LogTransport {
+    fn send(&self, message: &Message) -> Result<(), MailError> {
+        println!("    => Mail:\n{}", message);
+        Ok(())
+    }
+}
+
+
+/// Picks the transport named by `MAIL_TRANSPORT`: `log` (default), `maildir`,
+/// which delivers into `MAIL_DIR` (default `mail`), or `smtp`.
+fn transport_from_config() -> Box<Transport> {
+    match config::get("MAIL_TRANSPORT").as_ref().map(|s| s.as_str()) {
+        Some("maildir") => Box::new(maildir::MaildirTransport { dir: config::get("MAIL_DIR").unwrap_or("mail".into()).into() }),
+        Some("smtp") => Box::new(smtp::SmtpTransport::from_config()),
+        Some("log") | None => Box::new(LogTransport),
+        Some(s) => panic!("Unknown MAIL_TRANSPORT: {}", s),
+    }
+}
+
+lazy_static! {
+    pub static ref MAILER: Box<Transport> = transport_from_config();
+    /// Public address of the site, used to build links in mail.
+    pub static ref SITE_URL: String = config::get("SITE_URL").unwrap_or("http://localhost:8000".into());
+}
+
+
+/// Whether `addr` can go into a `To:` header as it is: one `@` with
+/// something on both sides and no control characters, so that it cannot
+/// add headers or recipients.
+pub fn valid_address(addr: &str) -> bool {
+    let mut parts = addr.split('@');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(local), Some(domain), None) => !local.is_empty() && !domain.is_empty()
+            && !addr.chars().any(|c| c.is_control()),
+        _ => false,
+    }
+}
+
+
+/// Sends `message` right away; handlers should `queue` instead. Mail queued
+/// for an invalid recipient is refused here too.
+pub fn send(message: &Message) -> Result<(), MailError> {
+    if !valid_address(&message.to) {
+        return Err(MailError(format!("invalid recipient {:?}", message.to)));
+    }
+    MAILER.send(message)
+}
+
+
+/// Stores `message` in the outbox for `deliver_due` to send, so that a slow or
+/// failing relay never holds up a request.
+pub fn queue(conn: &PgConnection, message: &Message) -> DBResult<OutboxMail> {
+    outbox::create(conn, &message.to, &message.subject, &message.body)
+}
+
+
+/// How long to wait before retrying a message that failed `attempts` times:
+/// one minute, doubling with each failure.
+pub fn retry_delay(attempts: i32) -> Duration {
+    Duration::minutes(1 << attempts.max(0).min(16))
+}
+
+
+/// Sends the outbox mail that is due; returns how many were sent.
+pub fn deliver_due(conn: &PgConnection) -> DBResult<usize> {
+    let now = UTC::now().naive_utc();
+    let mut sent = 0;
+    for mail in outbox::due(conn, now, BATCH_SIZE)? {
+        if !outbox::claim(conn, &mail, now + Duration::minutes(CLAIM_MINUTES))? {
+            continue;
+        }
+        let (id, attempts) = (mail.id, mail.attempts);
+        match send(&Message::from(mail)) {
+            Ok(_) => {
+                outbox::mark_sent(conn, id)?;
+                sent += 1;
+            },
+            Err(e) => {
+                if attempts + 1 >= outbox::MAX_ATTEMPTS {
+                    println!("    => Giving up on mail {}: {}", id, e);
+                }
+                let retry_at = UTC::now().naive_utc() + retry_delay(attempts);
+                outbox::mark_failed(conn, id, &e.0, retry_at)?;
+            },
+        }
+    }
+    Ok(sent)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert!(retry_delay(0) == Duration::minutes(1));
+        assert!(retry_delay(3) == Duration::minutes(8));
+        assert!(retry_delay(100) == retry_delay(16));
+    }
+
+    #[test]
+    fn test_valid_address() {
+        assert!(valid_address("a@b.c") && valid_address("first.last+tag@example.com"));
+        assert!(!valid_address("") && !valid_address("ab.c") && !valid_address("a@b@c"));
+        assert!(!valid_address("@b.c") && !valid_address("a@"));
+        assert!(!valid_address("a@b.c\r\nBcc: x@y.z") && !valid_address("a@b.c\n"));
+        assert!(send(&Message { to: "a@b.c\nBcc: x@y.z".into(), subject: "hi".into(), body: "".into() }).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+// SMTP client
+use lettre::email::EmailBuilder;
+use lettre::transport::EmailTransport;
+use lettre::transport::smtp::{SecurityLevel, SmtpTransportBuilder};
+
+use config;
+use mail::{Message, MailError, Transport};
+
+
+/// Sends mail through an SMTP relay.
+///
+/// A connection is opened for each message; the outbox worker sends few
+/// enough of them that keeping one alive is not worth the reconnect logic.
+pub struct SmtpTransport {
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<(String, String)>,
+    pub security: SecurityLevel,
+    pub from: String,
+}
+
+impl SmtpTransport {
+    /// Configures the relay from `.env` or the `[<env>]` section of `Rocket.toml`:
+    ///
+    /// * `SMTP_HOST` (required), `SMTP_PORT` (default 587)
+    /// * `SMTP_USER`, `SMTP_PASSWORD`: omitted for relays without authentication
+    /// * `SMTP_SECURITY`: `starttls` (default), `tls`, `opportunistic` or `none`
+    /// * `MAIL_FROM`: sender address
+    pub fn from_config() -> SmtpTransport {
+        let host = config::get("SMTP_HOST").expect("SMTP_HOST must be set for MAIL_TRANSPORT=smtp");
+        let port = config::get("SMTP_PORT")
+            .map(|p| p.parse().expect("SMTP_PORT must be a port number"))
+            .unwrap_or(587);
+        let credentials = match (config::get("SMTP_USER"), config::get("SMTP_PASSWORD")) {
+            (Some(user), Some(password)) => Some((user, password)),
+            _ => None,
+        };
+        let security = match config::get("SMTP_SECURITY").as_ref().map(|s| s.as_str()) {
+            Some("starttls") | None => SecurityLevel::AlwaysEncrypt,
+            Some("tls") => SecurityLevel::EncryptedWrapper,
+            Some("opportunistic") => SecurityLevel::Opportunistic,
+            Some("none") => SecurityLevel::NeverEncrypt,
+            Some(s) => panic!("Unknown SMTP_SECURITY: {}", s),
+        };
+        SmtpTransport {
+            host: host,
+            port: port,
+            credentials: credentials,
+            security: security,
+            from: config::get("MAIL_FROM").expect("MAIL_FROM must be set for MAIL_TRANSPORT=smtp"),
+        }
+    }
+}
+
+impl Transport for SmtpTransport {
+    fn send(&self, message: &Message) -> Result<(), MailError> {
+        let email = EmailBuilder::new()
+            .to(message.to.as_str())
+            .from(self.from.as_str())
+            .subject(&message.subject)
+            .body(&message.body)
+            .build()
+            .map_err(|e| MailError(e.to_string()))?;
+
+        let mut builder = SmtpTransportBuilder::new((self.host.as_str(), self.port))
+            .map_err(|e| MailError(e.to_string()))?
+            .security_level(self.security.clone());
+        if let Some((ref user, ref password)) = self.credentials {
+            builder = builder.credentials(user, password);
+        }
+        let mut mailer = builder.build();
+        let result = mailer.send(email).map(|_| ()).map_err(|e| MailError(e.to_string()));
+        mailer.close();
+        result
+    }
+}
@@ -0,0 +1,95 @@
+use mail::{Message, MailError, valid_address};
+
+
+/// A message with `{name}` placeholders in its subject and body.
+pub struct Template {
+    pub subject: &'static str,
+    pub body: &'static str,
+}
+
+impl Template {
+    /// Renders the template for `to`, filling in `vars`. Line breaks in the
+    /// subject become spaces so that values cannot add headers; a recipient
+    /// that could is refused.
+    pub fn render(&self, to: &str, vars: &[(&str, &str)]) -> Result<Message, MailError> {
+        if !valid_address(to) {
+            return Err(MailError(format!("invalid recipient {:?}", to)));
+        }
+        Ok(Message {
+            to: to.into(),
+            subject: render(self.subject, vars).replace(|c: char| c == '\r' || c == '\n', " "),
+            body: render(self.body, vars),
+        })
+    }
+}
+
+
+pub const VERIFY_VISITOR: Template = Template {
+    subject: "Please verify your email address",
+    body: "Hi {name},\n\nopen this link within {days} days to verify your address:\n{link}\n",
+};
+
+pub const REPLY_NOTIFICATION: Template = Template {
+    subject: "New comment on {title}",
+    body: "Hi {name},\n\n{author} commented on \"{title}\":\n\n{body}\n\nRead the discussion at {link}\n\n\
+           To stop getting mail about this post, open:\n{unsubscribe}\n",
+};
+
+pub const MODERATION_ALERT: Template = Template {
+    subject: "Comment awaiting moderation on {title}",
+    body: "{author} commented on \"{title}\" (spam score {score}):\n\n{body}\n\nReview the queue at {link}\n",
+};
+
+
+/// Replaces each `{name}` in `text` with its value in `vars`; `{{` and `}}`
+/// stand for literal braces and unknown names are left alone.
+pub fn render(text: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(|c| c == '{' || c == '}') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        let end = if tail.starts_with('{') { tail.find('}') } else { None };
+        let value = end.and_then(|end| vars.iter().find(|&&(k, _)| k == &tail[1..end]).map(|&(_, v)| (v, end)));
+        match value {
+            Some((v, end)) => {
+                out.push_str(v);
+                rest = &tail[end + 1..];
+            },
+            None => {
+                out.push_str(&tail[..1]);
+                rest = &tail[1..];
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let vars = [("name", "Meow"), ("link", "http://x/{name}")];
+        assert!(render("Hi {name}, see {link}", &vars) == "Hi Meow, see http://x/{name}");
+        assert!(render("{{name}} {unknown} {name", &vars) == "{name} {unknown} {name");
+
+        let message = VERIFY_VISITOR.render("a@b.c", &[("name", "Meow"), ("days", "2"), ("link", "http://x")]).unwrap();
+        assert!(message.to == "a@b.c" && message.subject == VERIFY_VISITOR.subject);
+        assert!(message.body == "Hi Meow,\n\nopen this link within 2 days to verify your address:\nhttp://x\n");
+
+        let message = REPLY_NOTIFICATION.render("a@b.c", &[("title", "Hi\r\nBcc: x@y.z")]).unwrap();
+        assert!(message.subject == "New comment on Hi  Bcc: x@y.z");
+        assert!(message.body.contains("\"Hi\r\nBcc: x@y.z\""));
+        assert!(REPLY_NOTIFICATION.render("a@b.c\r\nBcc: x@y.z", &[]).is_err());
+    }
+}
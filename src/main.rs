@@ -12,12 +12,25 @@ extern crate r2d2_diesel;
 extern crate dotenv;
 extern crate chrono;
 #[macro_use] extern crate lazy_static;
+extern crate crypto;
+extern crate rand;
+extern crate lettre;
 
 extern crate serde_json;
 #[macro_use] extern crate serde_derive;
 
 
 mod handlers;
+mod auth;
+mod config;
+mod mail;
+mod token;
+mod spam;
+mod notify;
+mod privacy;
+mod diff;
+mod scheduler;
+mod throttle;
 mod db;
 mod models;
 mod schema;
@@ -29,25 +42,93 @@ fn index() -> &'static str {
 }
 
 fn main() {
-    rocket::ignite()
+    token::check_secret();
+    auth::bootstrap_admin();
+
+    let rocket = rocket::ignite()
         .mount("/", routes![index,
+               handlers::auth::login,
+               handlers::auth::logout,
+               handlers::api_key::get_all,
+               handlers::api_key::create,
+               handlers::api_key::delete,
+               handlers::user::get_all,
+               handlers::user::create,
+               handlers::user::update,
+               handlers::user::delete,
                handlers::post::get_all,
+               handlers::post::get_page,
                handlers::post::get,
                handlers::post::create,
                handlers::post::publish,
+               handlers::post::unpublish,
+               handlers::post::schedule,
+               handlers::post::unschedule,
+               handlers::post::set_state,
+               handlers::post::transitions,
+               handlers::revision::get_all,
+               handlers::revision::get,
+               handlers::revision::get_diff,
+               handlers::revision::restore,
                handlers::post::update,
                handlers::post::delete,
+               handlers::tag::get_all,
+               handlers::tag::get_posts,
+               handlers::tag::get_page_posts,
+               handlers::tag::rename,
+               handlers::tag::merge,
                handlers::visitor::get_all,
+               handlers::visitor::get_page,
                handlers::visitor::get,
                handlers::visitor::create,
+               handlers::visitor::resend_verification,
+               handlers::visitor::verify,
                handlers::visitor::update,
                handlers::visitor::delete,
+               handlers::visitor::delete_with,
+               handlers::visitor::export,
+               handlers::visitor::erase,
+               handlers::visitor::audit,
                handlers::comment::get_all,
+               handlers::comment::get_page,
                handlers::comment::get,
+               handlers::comment::get_for_post,
+               handlers::comment::get_page_for_post,
+               handlers::comment::get_tree,
+               handlers::comment::get_tree_with,
                handlers::comment::create,
                handlers::comment::update,
                handlers::comment::delete,
+               handlers::comment::get_queue,
+               handlers::comment::get_queue_page,
+               handlers::comment::approve,
+               handlers::comment::reject,
+               handlers::comment::mark_spam,
+               handlers::comment::moderate,
+               handlers::comment::get_policy,
+               handlers::comment::set_policy,
+               handlers::comment::get_blacklist,
+               handlers::comment::set_blacklist,
+               handlers::subscription::confirm_unsubscribe,
+               handlers::subscription::unsubscribe,
+               handlers::trash::get_posts,
+               handlers::trash::get_comments,
+               handlers::trash::restore_post,
+               handlers::trash::restore_comment,
+               handlers::trash::purge_post,
+               handlers::trash::purge_comment,
+               handlers::trash::purge,
+               handlers::scheduler::status,
                ])
-        .catch(errors![handlers::errors::not_found])
-        .launch();
+        .catch(errors![handlers::errors::bad_request,
+                        handlers::errors::unauthorized,
+                        handlers::errors::forbidden,
+                        handlers::errors::not_found,
+                        handlers::errors::unprocessable_entity,
+                        handlers::errors::too_many_requests,
+                        handlers::errors::internal_error,
+                        handlers::errors::service_unavailable]);
+
+    scheduler::start();
+    rocket.launch();
 }
@@ -1,16 +1,68 @@
 use chrono::prelude::*;
 
 
-#[derive(Queryable, Serialize, Deserialize, Debug)]
+/// A post row; clients get it as a `PostView` with its tags.
+#[derive(Queryable, Debug)]
 pub struct Post {
     pub id: i32,
     pub title: String,
-    pub category: String,
     pub body: String,
     pub created: NaiveDateTime,
     pub last_edited: NaiveDateTime,
-    pub published: bool,
     pub deleted: bool,
+    pub uid: Option<i32>,
+    pub deleted_at: Option<NaiveDateTime>,
+    /// When a published or scheduled post goes live.
+    pub publish_at: Option<NaiveDateTime>,
+    /// When it was seen to go live; unset while scheduled.
+    pub published_at: Option<NaiveDateTime>,
+    /// Editorial state, see `db::post::State`.
+    pub state: String,
+}
+
+
+#[derive(Serialize)]
+pub struct PostView {
+    pub id: i32,
+    pub title: String,
+    /// Tag names in the order they were given.
+    pub categories: Vec<String>,
+    pub body: String,
+    pub created: NaiveDateTime,
+    pub last_edited: NaiveDateTime,
+    pub deleted: bool,
+    pub uid: Option<i32>,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub publish_at: Option<NaiveDateTime>,
+    pub published_at: Option<NaiveDateTime>,
+    pub state: String,
+    /// Whether the post is live, as before posts had a `state`.
+    pub published: bool,
+    /// Row version, as sent in `ETag` and expected in `If-Match`.
+    pub version: i64,
+}
+
+impl PostView {
+    pub fn new(post: Post, categories: Vec<String>) -> PostView {
+        let published = ::db::post::is_live(&post, UTC::now().naive_utc());
+        let version = ::db::version(post.last_edited);
+        PostView {
+            id: post.id,
+            title: post.title,
+            categories: categories,
+            body: post.body,
+            created: post.created,
+            last_edited: post.last_edited,
+            deleted: post.deleted,
+            uid: post.uid,
+            deleted_at: post.deleted_at,
+            publish_at: post.publish_at,
+            published_at: post.published_at,
+            state: post.state,
+            published: published,
+            version: version,
+        }
+    }
 }
 
 
@@ -20,8 +72,8 @@ use super::schema::posts;
 #[table_name="posts"]
 pub struct NewPost {
     pub title: String,
-    pub category: String,
     pub body: String,
+    pub uid: Option<i32>,
 }
 
 
@@ -32,6 +84,8 @@ pub struct Visitor {
     pub mail: String,
     pub site: Option<String>,
     pub created: NaiveDateTime,
+    pub verified_at: Option<NaiveDateTime>,
+    pub deleted: bool,
 }
 
 
@@ -54,16 +108,290 @@ pub struct Comment {
     pub body: String,
     pub created: NaiveDateTime,
     pub last_edited: NaiveDateTime,
-    pub deleted: bool,
+    pub parent_id: Option<i32>,
+    pub status: String,
+    /// Only sent to moderators; handlers clear it for everyone else.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spam_score: Option<f64>,
+    #[serde(skip_serializing)]
+    pub trained: Option<String>,
+    #[serde(skip_serializing)]
+    pub notified: bool,
+    pub deleted_at: Option<NaiveDateTime>,
+    /// Status to go back to when restored from the trash.
+    #[serde(skip_serializing)]
+    pub prior_status: Option<String>,
 }
 
 
 use super::schema::comments;
 
-#[derive(Insertable, Deserialize)]
+#[derive(Insertable)]
 #[table_name="comments"]
 pub struct NewComment {
     pub pid: i32,
     pub vid: i32,
     pub body: String,
+    pub parent_id: Option<i32>,
+    pub status: String,
+    pub spam_score: Option<f64>,
+}
+
+
+#[derive(Queryable, Serialize)]
+pub struct User {
+    pub id: i32,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub password: String,
+    pub created: NaiveDateTime,
+    pub role: String,
+}
+
+
+use super::schema::users;
+
+#[derive(Insertable)]
+#[table_name="users"]
+pub struct NewUser {
+    pub name: String,
+    pub password: String,
+    pub role: String,
+}
+
+
+#[derive(Queryable)]
+pub struct Session {
+    pub token: String,
+    pub uid: i32,
+    pub created: NaiveDateTime,
+    pub expires: NaiveDateTime,
+}
+
+
+use super::schema::sessions;
+
+#[derive(Insertable)]
+#[table_name="sessions"]
+pub struct NewSession {
+    pub token: String,
+    pub uid: i32,
+    pub expires: NaiveDateTime,
+}
+
+
+#[derive(Queryable, Serialize)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub key: String,
+    pub scopes: Vec<String>,
+    pub created: NaiveDateTime,
+    pub expires: Option<NaiveDateTime>,
+    pub last_used: Option<NaiveDateTime>,
+}
+
+
+use super::schema::api_keys;
+
+#[derive(Insertable)]
+#[table_name="api_keys"]
+pub struct NewApiKey {
+    pub name: String,
+    pub key: String,
+    pub scopes: Vec<String>,
+    pub expires: Option<NaiveDateTime>,
+}
+
+
+use super::schema::settings;
+
+#[derive(Queryable, Insertable)]
+#[table_name="settings"]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+}
+
+
+use super::schema::spam_tokens;
+
+#[derive(Queryable, Insertable)]
+#[table_name="spam_tokens"]
+pub struct SpamToken {
+    pub token: String,
+    pub spam: i32,
+    pub ham: i32,
+}
+
+
+use super::schema::rate_limits;
+
+#[derive(Queryable, Insertable)]
+#[table_name="rate_limits"]
+pub struct Bucket {
+    pub key: String,
+    pub tokens: f64,
+    pub updated: NaiveDateTime,
+}
+
+
+#[derive(Queryable, Serialize)]
+pub struct OutboxMail {
+    pub id: i32,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub created: NaiveDateTime,
+    pub attempts: i32,
+    pub next_attempt: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub sent_at: Option<NaiveDateTime>,
+}
+
+
+use super::schema::outbox;
+
+#[derive(Insertable)]
+#[table_name="outbox"]
+pub struct NewOutboxMail {
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+}
+
+
+#[derive(Queryable, Serialize)]
+pub struct Subscription {
+    pub vid: i32,
+    pub pid: i32,
+    pub created: NaiveDateTime,
+}
+
+
+use super::schema::subscriptions;
+
+#[derive(Insertable)]
+#[table_name="subscriptions"]
+pub struct NewSubscription {
+    pub vid: i32,
+    pub pid: i32,
+}
+
+
+#[derive(Queryable, Serialize)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub uid: Option<i32>,
+    pub action: String,
+    pub subject: String,
+    pub detail: String,
+    pub created: NaiveDateTime,
+}
+
+
+use super::schema::audit_log;
+
+#[derive(Insertable)]
+#[table_name="audit_log"]
+pub struct NewAuditEntry {
+    pub uid: Option<i32>,
+    pub action: String,
+    pub subject: String,
+    pub detail: String,
+}
+
+
+use super::schema::scheduled_tasks;
+
+#[derive(Queryable, Insertable, Serialize, Clone)]
+#[table_name="scheduled_tasks"]
+pub struct TaskRun {
+    pub name: String,
+    pub last_started: Option<NaiveDateTime>,
+    pub last_finished: Option<NaiveDateTime>,
+    pub last_result: Option<String>,
+    pub last_message: Option<String>,
+    pub runs: i32,
+}
+
+
+#[derive(Queryable, Serialize)]
+pub struct PostTransition {
+    pub id: i32,
+    pub pid: i32,
+    pub from_state: String,
+    pub to_state: String,
+    pub uid: Option<i32>,
+    pub created: NaiveDateTime,
+}
+
+
+use super::schema::post_transitions;
+
+#[derive(Insertable)]
+#[table_name="post_transitions"]
+pub struct NewPostTransition {
+    pub pid: i32,
+    pub from_state: String,
+    pub to_state: String,
+    pub uid: Option<i32>,
+}
+
+
+#[derive(Queryable, Serialize)]
+pub struct PostRevision {
+    pub id: i32,
+    pub pid: i32,
+    pub rev: i32,
+    pub title: String,
+    pub body: String,
+    pub uid: Option<i32>,
+    pub created: NaiveDateTime,
+    pub categories: Vec<String>,
+}
+
+
+use super::schema::post_revisions;
+
+#[derive(Insertable)]
+#[table_name="post_revisions"]
+pub struct NewPostRevision {
+    pub pid: i32,
+    pub rev: i32,
+    pub title: String,
+    pub body: String,
+    pub uid: Option<i32>,
+    pub categories: Vec<String>,
+}
+
+
+#[derive(Queryable, Serialize)]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+    pub slug: String,
+    pub created: NaiveDateTime,
+}
+
+
+use super::schema::tags;
+
+#[derive(Insertable)]
+#[table_name="tags"]
+pub struct NewTag {
+    pub name: String,
+    pub slug: String,
+}
+
+
+use super::schema::post_tags;
+
+#[derive(Queryable, Insertable)]
+#[table_name="post_tags"]
+pub struct PostTag {
+    pub pid: i32,
+    pub tid: i32,
+    pub position: i32,
 }
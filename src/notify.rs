@@ -0,0 +1,117 @@
+// DB ORM
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use chrono::Duration;
+
+use config;
+use models::{Comment, Visitor};
+use mail::{self, SITE_URL};
+use mail::template::{REPLY_NOTIFICATION, MODERATION_ALERT};
+use token;
+use db::{DBResult, comment, post, subscription, visitor};
+
+
+pub const UNSUBSCRIBE_PURPOSE: &'static str = "unsubscribe";
+/// Unsubscribe links outlive most threads, so old notifications keep working.
+const UNSUBSCRIBE_DAYS: i64 = 365;
+
+
+/// What an unsubscribe token for post `pid` is bound to besides the visitor id.
+pub fn unsubscribe_binding(pid: i32) -> String {
+    format!("post:{}", pid)
+}
+
+
+pub fn unsubscribe_link(v: &Visitor, pid: i32) -> String {
+    let token = token::sign(UNSUBSCRIBE_PURPOSE, v.id, &unsubscribe_binding(pid), Duration::days(UNSUBSCRIBE_DAYS));
+    format!("{}/post/{}/unsubscribe/{}", *SITE_URL, pid, token)
+}
+
+
+fn post_title(conn: &PgConnection, pid: i32) -> DBResult<String> {
+    Ok(post::get(conn, Some(pid), false, false)?.pop().map_or(String::new(), |p| p.title))
+}
+
+
+/// Queues mail about approved comment `c` to everyone subscribed to its post
+/// but its author. Only verified addresses are mailed, so that subscribing
+/// someone else's address gets them nothing until they confirm it.
+fn notify_subscribers(conn: &PgConnection, c: &Comment) -> DBResult<usize> {
+    if !comment::mark_notified(conn, c.id)? {
+        return Ok(0);
+    }
+
+    let vids: Vec<i32> = subscription::subscribers(conn, c.pid)?.into_iter()
+        .filter(|&vid| vid != c.vid)
+        .collect();
+    if vids.is_empty() {
+        return Ok(0);
+    }
+    let title = post_title(conn, c.pid)?;
+    let author = visitor::get(conn, Some(c.vid), false)?.pop().map_or(String::new(), |v| v.name);
+    let link = format!("{}/post/{}", *SITE_URL, c.pid);
+
+    let mut queued = 0;
+    for v in visitor::get_many(conn, &vids)?.into_iter().filter(|v| v.verified_at.is_some() && !v.deleted) {
+        let unsubscribe = unsubscribe_link(&v, c.pid);
+        let message = REPLY_NOTIFICATION.render(&v.mail, &[("name", v.name.as_str()),
+                                                           ("author", author.as_str()),
+                                                           ("title", title.as_str()),
+                                                           ("body", c.body.as_str()),
+                                                           ("link", link.as_str()),
+                                                           ("unsubscribe", unsubscribe.as_str())]);
+        match message {
+            Ok(message) => { mail::queue(conn, &message)?; },
+            Err(e) => {
+                println!("    => Not notifying visitor {}: {}", v.id, e);
+                continue;
+            },
+        }
+        queued += 1;
+    }
+    Ok(queued)
+}
+
+
+/// Tells subscribers about whichever of comments `ids` are newly approved.
+/// Failures are only logged; the comments themselves are already saved.
+pub fn approved(conn: &PgConnection, ids: &[i32]) {
+    let result = comment::get_many(conn, ids).and_then(|comments| {
+        for c in comments.iter().filter(|c| c.status == comment::Status::Approved.as_str()) {
+            conn.transaction(|| notify_subscribers(conn, c))?;
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        println!("    => Failed to notify subscribers of comments {:?}: {}", ids, e);
+    }
+}
+
+
+/// Alerts `ADMIN_MAIL`, if set, that comment `c` waits for moderation.
+pub fn pending(conn: &PgConnection, c: &Comment, author: &Visitor) {
+    let to = match config::get("ADMIN_MAIL") {
+        Some(to) => to,
+        None => return,
+    };
+    let result = post_title(conn, c.pid).and_then(|title| {
+        let score = c.spam_score.map_or("unknown".into(), |s| format!("{:.2}", s));
+        let link = format!("{}/comment/queue", *SITE_URL);
+        let message = MODERATION_ALERT.render(&to, &[("author", author.name.as_str()),
+                                                     ("title", title.as_str()),
+                                                     ("score", score.as_str()),
+                                                     ("body", c.body.as_str()),
+                                                     ("link", link.as_str())]);
+        match message {
+            Ok(message) => mail::queue(conn, &message).map(|_| ()),
+            Err(e) => {
+                println!("    => ADMIN_MAIL is not a valid address: {}", e);
+                Ok(())
+            },
+        }
+    });
+    if let Err(e) = result {
+        println!("    => Failed to alert moderators of comment {}: {}", c.id, e);
+    }
+}
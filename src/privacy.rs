@@ -0,0 +1,144 @@
+// DB ORM
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+// Timestamp
+use chrono::prelude::*;
+
+use models::{AuditEntry, Comment, Subscription, Visitor};
+use db::{DBResult, Error, audit, comment, outbox, subscription, visitor};
+
+
+/// What happens to an erased visitor's comments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentErasure {
+    /// Keep them, attributed to the scrubbed visitor.
+    Anonymize,
+    /// Delete them, leaving blank placeholders where others replied.
+    Remove,
+}
+
+impl CommentErasure {
+    pub fn parse(s: &str) -> Option<CommentErasure> {
+        match s {
+            "anonymize" => Some(CommentErasure::Anonymize),
+            "remove" => Some(CommentErasure::Remove),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CommentErasure::Anonymize => "anonymize",
+            CommentErasure::Remove => "remove",
+        }
+    }
+}
+
+
+/// Everything stored about one visitor.
+#[derive(Serialize)]
+pub struct Export {
+    pub visitor: Visitor,
+    pub comments: Vec<Comment>,
+    pub subscriptions: Vec<Subscription>,
+    pub exported: NaiveDateTime,
+}
+
+
+fn subject(vid: i32) -> String {
+    format!("visitor:{}", vid)
+}
+
+
+/// Collects everything tied to visitor `vid` and records that user `uid` did.
+pub fn export(conn: &PgConnection, vid: i32, uid: Option<i32>) -> DBResult<Export> {
+    let v = visitor::get(conn, Some(vid), false)?.pop().ok_or(Error::RecordNotFound)?;
+    let export = Export {
+        visitor: v,
+        comments: comment::get_by_visitor(conn, vid)?,
+        subscriptions: subscription::for_visitor(conn, vid)?,
+        exported: UTC::now().naive_utc(),
+    };
+    audit::record(conn, uid, "visitor.export", &subject(vid), "")?;
+    Ok(export)
+}
+
+
+/// Erases visitor `vid` on behalf of user `uid`: scrubs their details, drops
+/// their subscriptions and mail, and handles their comments as asked. Either
+/// all of it happens, audit record included, or none of it.
+///
+/// Returns how many comments the visitor had.
+pub fn erase(conn: &PgConnection, vid: i32, comments: CommentErasure, uid: Option<i32>) -> DBResult<usize> {
+    conn.transaction(|| {
+        let v = visitor::get(conn, Some(vid), false)?.pop().ok_or(Error::RecordNotFound)?;
+        let num = match comments {
+            CommentErasure::Anonymize => comment::get_by_visitor(conn, vid)?.len(),
+            CommentErasure::Remove => comment::remove_by_visitor(conn, vid)?,
+        };
+        let subs = subscription::unsubscribe_all(conn, vid)?;
+        let mails = outbox::delete_for_recipient(conn, &v.mail)?;
+        visitor::anonymize(conn, vid)?;
+
+        let detail = json!({
+            "comments": comments.as_str(),
+            "comment_count": num,
+            "subscriptions": subs,
+            "mails": mails,
+        });
+        audit::record(conn, uid, "visitor.erase", &subject(vid), &detail.to_string())?;
+        Ok(num)
+    })
+}
+
+
+/// The audit trail of visitor `vid`, newest first.
+pub fn history(conn: &PgConnection, vid: i32) -> DBResult<Vec<AuditEntry>> {
+    audit::for_subject(conn, &subject(vid))
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use db::comment::Status;
+    use db::post;
+
+    #[test]
+    fn test_erase() {
+        use db::DB_POOL;
+        let ref conn = DB_POOL.get().unwrap();
+
+        let post = post::create(conn, "erasure", None, "body", None).unwrap();
+        let gone = visitor::create(conn, "gone", "erase@test.com", Some("http://gone.example".into())).unwrap();
+        let other = visitor::create(conn, "other", "erase-other@test.com", None).unwrap();
+        let top = comment::create(conn, post.id, gone.id, "mine", None, Status::Approved, None).unwrap();
+        let reply = comment::create(conn, post.id, other.id, "reply", Some(top.id), Status::Approved, None).unwrap();
+        let leaf = comment::create(conn, post.id, gone.id, "leaf", Some(reply.id), Status::Approved, None).unwrap();
+        subscription::subscribe(conn, gone.id, post.id).unwrap();
+
+        let data = export(conn, gone.id, None).unwrap();
+        assert!(data.visitor.mail == "erase@test.com" && data.comments.len() == 2 && data.subscriptions.len() == 1);
+        assert!(export(conn, -1, None).err() == Some(Error::RecordNotFound));
+
+        assert!(erase(conn, gone.id, CommentErasure::Remove, None).unwrap() == 2);
+        let v = visitor::get(conn, Some(gone.id), false).unwrap().pop().unwrap();
+        assert!(v.name == "[erased]" && v.site.is_none() && !v.mail.contains("erase@test.com"));
+        let left = comment::get_by_visitor(conn, gone.id).unwrap();
+        assert!(left.len() == 1 && left[0].id == top.id && left[0].body == "" && left[0].status == "deleted");
+        assert!(comment::get_many(conn, &[leaf.id]).unwrap().is_empty());
+        assert!(subscription::for_visitor(conn, gone.id).unwrap().is_empty());
+        let log = history(conn, gone.id).unwrap();
+        assert!(log[0].action == "visitor.erase" && log[1].action == "visitor.export");
+
+        comment::set_status(conn, &[reply.id], Status::Deleted).unwrap();
+        comment::purge(conn).unwrap();
+        visitor::delete(conn, gone.id, visitor::Cascade::Refuse).unwrap();
+        visitor::delete(conn, other.id, visitor::Cascade::Refuse).unwrap();
+        visitor::purge(conn).unwrap();
+        post::delete(conn, post.id, None).unwrap();
+        post::purge(conn).unwrap();
+    }
+}
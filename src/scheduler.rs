@@ -0,0 +1,354 @@
+// DB ORM
+use diesel::pg::PgConnection;
+
+// Timestamp
+use chrono::prelude::*;
+use chrono::Duration;
+
+use rocket_contrib::Value;
+
+use std::cmp;
+use std::thread;
+
+use config;
+use mail;
+use models::TaskRun;
+use db::{DB_POOL, DBResult, comment, post, task, user, visitor};
+
+
+/// When a task runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    /// `@every <n>s`, `<n>m` or `<n>h`.
+    Every(Duration),
+    /// Five cron fields: minute, hour, day of month, month, day of week.
+    Cron(Cron),
+}
+
+/// Which minutes, hours, etc. a cron schedule matches, indexed by value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    /// Sunday is 0.
+    weekdays: Vec<bool>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+
+/// Parses one cron field of comma-separated `*`, `n`, `a-b`, each optionally
+/// followed by `/step`, allowing values from `min` to `max`.
+fn parse_field(field: &str, min: u32, max: u32) -> Option<Vec<bool>> {
+    let mut set = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let mut pieces = part.splitn(2, '/');
+        let range = pieces.next().unwrap_or("");
+        let step = match pieces.next().map(|s| s.parse::<u32>()) {
+            Some(Ok(n)) if n > 0 => n,
+            Some(_) => return None,
+            None => 1,
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else {
+            let mut bounds = range.splitn(2, '-').map(|b| b.parse::<u32>().ok());
+            match (bounds.next(), bounds.next()) {
+                (Some(Some(start)), Some(Some(end))) => (start, end),
+                (Some(Some(start)), None) => (start, if step > 1 { max } else { start }),
+                _ => return None,
+            }
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        let mut value = start;
+        while value <= end {
+            set[value as usize] = true;
+            value += step;
+        }
+    }
+    Some(set)
+}
+
+
+impl Cron {
+    /// As in cron, a day matches either field when both are restricted.
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days[date.day() as usize];
+        let weekday = self.weekdays[date.weekday().num_days_from_sunday() as usize];
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+
+    /// The first matching minute after `t`, looking up to four years ahead.
+    fn next_after(&self, t: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut t = t.date().and_hms(t.hour(), t.minute(), 0) + Duration::minutes(1);
+        let limit = t + Duration::days(4 * 366);
+        while t < limit {
+            if !self.months[t.month() as usize] {
+                let (year, month) = if t.month() == 12 { (t.year() + 1, 1) } else { (t.year(), t.month() + 1) };
+                t = NaiveDate::from_ymd(year, month, 1).and_hms(0, 0, 0);
+            } else if !self.day_matches(t.date()) {
+                t = t.date().succ().and_hms(0, 0, 0);
+            } else if !self.hours[t.hour() as usize] {
+                t = t.date().and_hms(t.hour(), 0, 0) + Duration::hours(1);
+            } else if !self.minutes[t.minute() as usize] {
+                t = t + Duration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+
+impl Schedule {
+    /// Parses `@every 30s`, `@every 5m`, `@every 1h` or five cron fields
+    /// such as `30 3 * * *`; times are UTC.
+    pub fn parse(s: &str) -> Option<Schedule> {
+        let s = s.trim();
+        if s.starts_with("@every ") {
+            let spec = s["@every ".len()..].trim();
+            let unit = match spec.chars().last() {
+                Some(c) => c,
+                None => return None,
+            };
+            let n = match spec[..spec.len() - unit.len_utf8()].parse::<i64>() {
+                Ok(n) if n > 0 => n,
+                _ => return None,
+            };
+            return match unit {
+                's' => Some(Schedule::Every(Duration::seconds(n))),
+                'm' => Some(Schedule::Every(Duration::minutes(n))),
+                'h' => Some(Schedule::Every(Duration::hours(n))),
+                _ => None,
+            };
+        }
+
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return None;
+        }
+        match (parse_field(fields[0], 0, 59), parse_field(fields[1], 0, 23), parse_field(fields[2], 1, 31),
+               parse_field(fields[3], 1, 12), parse_field(fields[4], 0, 7)) {
+            (Some(minutes), Some(hours), Some(days), Some(months), Some(mut weekdays)) => {
+                // Both 0 and 7 mean Sunday.
+                if weekdays[7] {
+                    weekdays[0] = true;
+                }
+                weekdays.truncate(7);
+                Some(Schedule::Cron(Cron {
+                    minutes: minutes,
+                    hours: hours,
+                    days: days,
+                    months: months,
+                    weekdays: weekdays,
+                    any_day: fields[2] == "*",
+                    any_weekday: fields[4] == "*",
+                }))
+            },
+            _ => None,
+        }
+    }
+
+    /// When to run next after a run due at `t`; `None` if never again.
+    pub fn next_after(&self, t: NaiveDateTime) -> Option<NaiveDateTime> {
+        match *self {
+            Schedule::Every(interval) => Some(t + interval),
+            Schedule::Cron(ref cron) => cron.next_after(t),
+        }
+    }
+}
+
+
+/// A periodic maintenance job; `run` returns a summary for the status page.
+pub struct Task {
+    pub name: &'static str,
+    /// Used unless `SCHEDULE_<NAME>` is set; `off` disables the task.
+    pub default_schedule: &'static str,
+    run: fn(&PgConnection) -> DBResult<String>,
+}
+
+pub static TASKS: &'static [Task] = &[
+    Task { name: "mail_outbox", default_schedule: "@every 10s", run: send_mail },
+    Task { name: "purge_trash", default_schedule: "30 3 * * *", run: purge_trash },
+    Task { name: "publish_posts", default_schedule: "@every 1m", run: publish_posts },
+];
+
+
+fn send_mail(conn: &PgConnection) -> DBResult<String> {
+    Ok(format!("sent {} mails", mail::deliver_due(conn)?))
+}
+
+
+/// Records when posts scheduled with `publish_at` went live.
+fn publish_posts(conn: &PgConnection) -> DBResult<String> {
+    let posts = post::mark_live(conn, UTC::now().naive_utc())?;
+    let ids: Vec<String> = posts.iter().map(|p| p.id.to_string()).collect();
+    Ok(format!("published {} posts: [{}]", ids.len(), ids.join(", ")))
+}
+
+
+lazy_static! {
+    /// How long deleted posts and comments are kept: `TRASH_RETENTION_DAYS`,
+    /// default 30.
+    static ref TRASH_RETENTION: Duration = Duration::days(config::get("TRASH_RETENTION_DAYS")
+        .map(|d| match d.parse::<i64>() {
+            Ok(days) if days >= 0 => days,
+            _ => panic!("TRASH_RETENTION_DAYS must be a number of days"),
+        })
+        .unwrap_or(30));
+}
+
+
+/// Purges posts and comments deleted longer than `TRASH_RETENTION` ago,
+/// deleted visitors without comments and expired sessions.
+fn purge_trash(conn: &PgConnection) -> DBResult<String> {
+    let before = UTC::now().naive_utc() - *TRASH_RETENTION;
+    let comments = comment::purge_before(conn, before)?;
+    let posts = post::purge_before(conn, before)?;
+    let visitors = visitor::purge(conn)?;
+    let sessions = user::purge_sessions(conn)?;
+    Ok(format!("purged {} posts, {} comments, {} visitors and {} sessions", posts, comments, visitors, sessions))
+}
+
+
+impl Task {
+    /// The configured schedule text, `off` when disabled.
+    pub fn schedule_text(&self) -> String {
+        config::get(&format!("SCHEDULE_{}", self.name.to_uppercase()))
+            .unwrap_or(self.default_schedule.into())
+    }
+
+    pub fn schedule(&self) -> Option<Schedule> {
+        let text = self.schedule_text();
+        if text.trim() == "off" {
+            return None;
+        }
+        Some(Schedule::parse(&text).expect(&format!("Invalid schedule for task {}: {}", self.name, text)))
+    }
+
+    /// Advisory lock key shared by every instance: FNV-1a of the task name.
+    fn lock_key(&self) -> i64 {
+        self.name.bytes().fold(0xcbf29ce484222325u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3)) as i64
+    }
+
+    /// Runs the task for its occurrence due at `due`, unless another instance
+    /// holds the task's advisory lock or already started this occurrence.
+    /// Returns the recorded run, or `None` if it was skipped.
+    pub fn run_once(&self, due: NaiveDateTime) -> DBResult<Option<TaskRun>> {
+        let conn = DB_POOL.get()?;
+        let key = self.lock_key();
+        if !task::try_lock(&*conn, key)? {
+            return Ok(None);
+        }
+        let _lock = TaskLock { conn: &*conn, key: key };
+        if task::get(&*conn, self.name)?.and_then(|r| r.last_started).map_or(false, |t| t >= due) {
+            return Ok(None);
+        }
+        task::started(&*conn, self.name, UTC::now().naive_utc())?;
+        let (ok, message) = match (self.run)(&*conn) {
+            Ok(summary) => (true, summary),
+            Err(e) => (false, e.to_string()),
+        };
+        task::finished(&*conn, self.name, UTC::now().naive_utc(), ok, &message).map(Some)
+    }
+}
+
+
+/// A task's advisory lock, released when dropped so that the pooled
+/// connection never keeps it after an error or a panic.
+struct TaskLock<'a> {
+    conn: &'a PgConnection,
+    key: i64,
+}
+
+impl<'a> Drop for TaskLock<'a> {
+    fn drop(&mut self) {
+        if let Err(e) = task::unlock(self.conn, self.key) {
+            println!("    => Failed to release task lock {}: {}", self.key, e);
+        }
+    }
+}
+
+
+/// Starts a thread for each enabled task that runs it on schedule.
+///
+/// Call after `rocket::ignite()` so that `Rocket.toml` settings are visible;
+/// invalid schedules and task settings panic here rather than in the threads.
+pub fn start() {
+    let schedules: Vec<(&'static Task, Schedule)> = TASKS.iter()
+        .filter_map(|t| t.schedule().map(|schedule| (t, schedule)))
+        .collect();
+    // Read lazily by `purge_trash`; forced now so that a bad value panics here.
+    let _ = *TRASH_RETENTION;
+
+    for (t, schedule) in schedules {
+        thread::spawn(move || {
+            let mut due = schedule.next_after(UTC::now().naive_utc());
+            while let Some(at) = due {
+                if let Ok(wait) = at.signed_duration_since(UTC::now().naive_utc()).to_std() {
+                    thread::sleep(wait);
+                }
+                if let Err(e) = t.run_once(at) {
+                    println!("    => Task {} failed: {}", t.name, e);
+                }
+                due = schedule.next_after(cmp::max(at, UTC::now().naive_utc()));
+            }
+        });
+    }
+}
+
+
+/// Each task's schedule, next run and the last run recorded by any instance.
+pub fn status(conn: &PgConnection) -> DBResult<Vec<Value>> {
+    let runs = task::all(conn)?;
+    let now = UTC::now().naive_utc();
+    Ok(TASKS.iter().map(|t| {
+        let last = runs.iter().find(|r| r.name == t.name);
+        json!({
+            "name": t.name,
+            "schedule": t.schedule_text(),
+            "next_run": t.schedule().and_then(|s| s.next_after(now)),
+            "last_started": last.and_then(|r| r.last_started),
+            "last_finished": last.and_then(|r| r.last_finished),
+            "last_result": last.and_then(|r| r.last_result.clone()),
+            "last_message": last.and_then(|r| r.last_message.clone()),
+            "runs": last.map_or(0, |r| r.runs),
+        })
+    }).collect())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        let t = NaiveDate::from_ymd(2017, 7, 19).and_hms(3, 30, 20);
+        let at = |d: u32, h: u32, m: u32| Some(NaiveDate::from_ymd(2017, 7, d).and_hms(h, m, 0));
+
+        let every = Schedule::parse("@every 10s").unwrap();
+        assert!(every.next_after(t) == Some(t + Duration::seconds(10)));
+        assert!(Schedule::parse("@every 0s").is_none() && Schedule::parse("@every 5d").is_none());
+
+        assert!(Schedule::parse("30 3 * * *").unwrap().next_after(t) == at(20, 3, 30));
+        assert!(Schedule::parse("*/15 * * * *").unwrap().next_after(t) == at(19, 3, 45));
+        assert!(Schedule::parse("0 9-17/4 * * *").unwrap().next_after(t) == at(19, 9, 0));
+        // 2017-07-19 is a Wednesday; 0 and 7 are both Sunday.
+        assert!(Schedule::parse("0 0 * * 7").unwrap().next_after(t) == at(23, 0, 0));
+        assert!(Schedule::parse("0 0 1 * 5").unwrap().next_after(t) == at(21, 0, 0));
+        assert!(Schedule::parse("0 0 30 2 *").unwrap().next_after(t).is_none());
+
+        assert!(Schedule::parse("60 * * * *").is_none() && Schedule::parse("* * * *").is_none());
+        assert!(Schedule::parse("5-1 * * * *").is_none() && Schedule::parse("*/0 * * * *").is_none());
+    }
+}
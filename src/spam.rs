@@ -0,0 +1,271 @@
+// DB ORM
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+
+use std::collections::{HashMap, HashSet};
+
+use db::{DBResult, comment, setting};
+use db::comment::Status;
+use db::spam::{self, Label};
+
+
+/// Comments scoring at least this much are filed as spam.
+pub const SPAM_THRESHOLD: f64 = 0.9;
+/// Comments scoring at least this much wait for a moderator whatever the policy.
+pub const REVIEW_THRESHOLD: f64 = 0.5;
+
+/// Forms submitted faster than this were most likely filled in by a bot.
+const MIN_SUBMIT_SECONDS: i64 = 3;
+const MAX_TOKENS: usize = 200;
+/// Both sides of the model need this many comments before it is trusted.
+const MIN_DOCUMENTS: i32 = 10;
+/// How many of the most telling tokens are combined into the Bayes score.
+const INTERESTING_TOKENS: usize = 15;
+
+const WORDS_SETTING: &'static str = "spam_words";
+const DOMAINS_SETTING: &'static str = "spam_domains";
+
+
+/// What a comment form submitted, beyond the comment itself.
+pub struct Submission<'a> {
+    pub body: &'a str,
+    /// Hidden form field that people never see and bots tend to fill in.
+    pub honeypot: Option<&'a str>,
+    /// Seconds between rendering the form and submitting it, if known.
+    pub elapsed: Option<i64>,
+}
+
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Blacklist {
+    pub words: Vec<String>,
+    pub domains: Vec<String>,
+}
+
+
+fn read_list(conn: &PgConnection, key: &str) -> DBResult<Vec<String>> {
+    let value = setting::get(conn, key)?.unwrap_or_default();
+    Ok(value.lines().map(|l| l.trim().to_lowercase()).filter(|l| !l.is_empty()).collect())
+}
+
+pub fn blacklist(conn: &PgConnection) -> DBResult<Blacklist> {
+    Ok(Blacklist {
+        words: read_list(conn, WORDS_SETTING)?,
+        domains: read_list(conn, DOMAINS_SETTING)?,
+    })
+}
+
+pub fn set_blacklist(conn: &PgConnection, list: &Blacklist) -> DBResult<()> {
+    setting::set(conn, WORDS_SETTING, &list.words.join("\n"))?;
+    setting::set(conn, DOMAINS_SETTING, &list.domains.join("\n"))?;
+    Ok(())
+}
+
+
+/// Hosts of the links in `body`, lowercased and without `www.`.
+pub fn link_domains(body: &str) -> Vec<String> {
+    body.split(|c: char| c.is_whitespace() || "\"'<>()[]".contains(c))
+        .filter_map(|word| {
+            let lower = word.to_lowercase();
+            let rest = if lower.starts_with("http://") {
+                lower["http://".len()..].to_string()
+            } else if lower.starts_with("https://") {
+                lower["https://".len()..].to_string()
+            } else if lower.starts_with("www.") {
+                lower
+            } else {
+                return None;
+            };
+            let host = rest.split(|c| c == '/' || c == '?' || c == '#' || c == ':').next().unwrap_or("");
+            let host = host.trim_left_matches("www.");
+            if host.is_empty() { None } else { Some(host.to_string()) }
+        })
+        .collect()
+}
+
+
+/// Splits a comment into the distinct tokens the Bayes model counts.
+///
+/// Words are lowercased; linked hosts are added as `domain:<host>`.
+pub fn tokenize(body: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let words = body.split(|c: char| !(c.is_alphanumeric() || c == '\'' || c == '$'))
+        .map(|w| w.trim_matches('\'').to_lowercase())
+        .filter(|w| w.chars().count() >= 2 && w.chars().count() <= 24);
+    let domains = link_domains(body).into_iter().map(|d| format!("domain:{}", d));
+
+    words.chain(domains)
+        .filter(|t| seen.insert(t.clone()))
+        .take(MAX_TOKENS)
+        .collect()
+}
+
+
+/// Whether `word` occurs in `text` as a whole word or phrase, so that a
+/// blacklisted word does not flag longer words containing it.
+fn contains_word(text: &str, word: &str) -> bool {
+    let is_word = |c: Option<char>| c.map_or(false, |c| c.is_alphanumeric());
+    !word.is_empty() && text.match_indices(word).any(|(i, _)| {
+        !is_word(text[..i].chars().next_back()) && !is_word(text[i + word.len()..].chars().next())
+    })
+}
+
+
+/// Scores the tell-tale signs of spam from 0 (none) to 1.
+pub fn heuristic_score(sub: &Submission, blacklist: &Blacklist) -> f64 {
+    if sub.honeypot.map_or(false, |h| !h.trim().is_empty()) {
+        return 1.0;
+    }
+
+    let mut score = 0.0;
+    if let Some(elapsed) = sub.elapsed {
+        if elapsed < MIN_SUBMIT_SECONDS {
+            score += 0.5;
+        }
+    }
+
+    let domains = link_domains(sub.body);
+    score += (domains.len() as f64 * 0.15).min(0.6);
+    score += 0.5 * domains.iter()
+        .filter(|d| blacklist.domains.iter().any(|b| *d == b || d.ends_with(&format!(".{}", b))))
+        .count() as f64;
+
+    let lower = sub.body.to_lowercase();
+    score += 0.5 * blacklist.words.iter().filter(|w| contains_word(&lower, &w.to_lowercase())).count() as f64;
+
+    let letters: Vec<char> = sub.body.chars().filter(|c| c.is_alphabetic()).collect();
+    if letters.len() >= 20 {
+        let caps = letters.iter().filter(|c| c.is_uppercase()).count();
+        if caps as f64 / letters.len() as f64 > 0.6 {
+            score += 0.3;
+        }
+    }
+
+    score.min(1.0)
+}
+
+
+/// Probability that `tokens` are spam, or `None` while the model has too little training.
+///
+/// Each token's spam probability is smoothed towards 0.5 by how rarely it was
+/// seen, and the most telling ones are combined as in naive Bayes.
+pub fn bayes_score(tokens: &[String], counts: &HashMap<String, (i32, i32)>,
+                   spam_docs: i32, ham_docs: i32) -> Option<f64> {
+    if spam_docs < MIN_DOCUMENTS || ham_docs < MIN_DOCUMENTS {
+        return None;
+    }
+
+    let mut probs: Vec<f64> = tokens.iter()
+        .filter_map(|t| counts.get(t))
+        .filter_map(|&(spam, ham)| {
+            let (spam, ham) = (spam.max(0) as f64, ham.max(0) as f64);
+            let spam_freq = (spam / spam_docs as f64).min(1.0);
+            let ham_freq = (ham / ham_docs as f64).min(1.0);
+            if spam_freq + ham_freq == 0.0 {
+                return None;
+            }
+            let p = spam_freq / (spam_freq + ham_freq);
+            let seen = spam + ham;
+            Some(((0.5 + seen * p) / (1.0 + seen)).max(0.01).min(0.99))
+        })
+        .collect();
+    if probs.is_empty() {
+        return Some(0.5);
+    }
+
+    probs.sort_by(|a, b| (b - 0.5).abs().partial_cmp(&(a - 0.5).abs()).unwrap());
+    probs.truncate(INTERESTING_TOKENS);
+    let (ln_spam, ln_ham) = probs.iter()
+        .fold((0.0, 0.0), |(s, h), p| (s + p.ln(), h + (1.0 - p).ln()));
+    Some(1.0 / (1.0 + (ln_ham - ln_spam).exp()))
+}
+
+
+/// Scores a submission from 0 (ham) to 1 (spam); whichever of the heuristics
+/// and the trained model is more suspicious wins.
+pub fn score(conn: &PgConnection, sub: &Submission) -> DBResult<f64> {
+    let heuristic = heuristic_score(sub, &blacklist(conn)?);
+    let tokens = tokenize(sub.body);
+    let (spam_docs, ham_docs) = spam::corpus(conn)?;
+    let bayes = bayes_score(&tokens, &spam::counts(conn, &tokens)?, spam_docs, ham_docs);
+    Ok(bayes.map_or(heuristic, |b| b.max(heuristic)))
+}
+
+
+/// Files a comment scoring `score` that would otherwise start as `status`.
+///
+/// Visitors with a verified address are only held back when the comment
+/// scores as spam outright.
+pub fn route(score: f64, status: Status, verified: bool) -> Status {
+    if score >= SPAM_THRESHOLD {
+        Status::Spam
+    } else if score >= REVIEW_THRESHOLD && !verified {
+        Status::Pending
+    } else {
+        status
+    }
+}
+
+
+/// Moves comments to `status` and trains the model on the decision.
+pub fn moderate(conn: &PgConnection, ids: &[i32], status: Status) -> DBResult<usize> {
+    conn.transaction(|| {
+        let num = comment::set_status(conn, ids, status)?;
+        learn(conn, ids)?;
+        Ok(num)
+    })
+}
+
+
+/// Trains the model on moderator decisions: approved comments are ham and
+/// comments marked as spam are spam. Other statuses undo earlier training.
+pub fn learn(conn: &PgConnection, ids: &[i32]) -> DBResult<()> {
+    for c in comment::get_many(conn, ids)? {
+        let label = match Status::parse(&c.status) {
+            Some(Status::Approved) => Some(Label::Ham),
+            Some(Status::Spam) => Some(Label::Spam),
+            _ => None,
+        };
+        spam::learn(conn, &c, &tokenize(&c.body), label)?;
+    }
+    Ok(())
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_heuristics() {
+        let list = Blacklist { words: vec!["viagra".into()], domains: vec!["pills.example".into()] };
+        let sub = |body| Submission { body: body, honeypot: None, elapsed: Some(30) };
+
+        assert!(heuristic_score(&sub("Nice post, thanks!"), &list) == 0.0);
+        assert!(contains_word("buy viagra!", "viagra") && !contains_word("viagrarian", "viagra"));
+        assert!(heuristic_score(&sub("Buy VIAGRA at http://www.shop.pills.example/buy"), &list) >= SPAM_THRESHOLD);
+        let bot = Submission { body: "Nice post", honeypot: Some("http://x.example"), elapsed: None };
+        assert!(heuristic_score(&bot, &list) == 1.0);
+        let hasty = Submission { body: "Nice post", honeypot: Some(""), elapsed: Some(1) };
+        assert!(route(heuristic_score(&hasty, &list), Status::Approved, false) == Status::Pending);
+        assert!(route(heuristic_score(&hasty, &list), Status::Approved, true) == Status::Approved);
+
+        assert!(link_domains("see <a href=\"https://WWW.Example.com:80/a\">x</a>") == vec!["example.com".to_string()]);
+        assert!(tokenize("Don't don't SHOUT www.a.example") == vec!["don't", "shout", "www", "example", "domain:a.example"]);
+    }
+
+    #[test]
+    fn test_bayes() {
+        let mut counts = HashMap::new();
+        counts.insert("pills".to_string(), (9, 0));
+        counts.insert("cheap".to_string(), (8, 1));
+        counts.insert("thanks".to_string(), (0, 9));
+        let spammy: Vec<String> = vec!["cheap".into(), "pills".into()];
+        let hammy: Vec<String> = vec!["thanks".into(), "unknown".into()];
+
+        assert!(bayes_score(&spammy, &counts, 5, 10).is_none());
+        assert!(bayes_score(&spammy, &counts, 10, 10).unwrap() > SPAM_THRESHOLD);
+        assert!(bayes_score(&hammy, &counts, 10, 10).unwrap() < 0.1);
+    }
+}
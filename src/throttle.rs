@@ -0,0 +1,315 @@
+// Environment
+use dotenv::dotenv;
+use std::env;
+
+// Timestamp
+use chrono::prelude::*;
+
+// Provides client addresses for Rocket
+use rocket::request::{Outcome, FromRequest};
+use rocket::Outcome::Success;
+use rocket::Request;
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use db::{DB_POOL, DBResult, Error, rate_limit};
+
+
+/// Buckets the in-process store keeps before dropping the ones that refilled.
+const MAX_BUCKETS: usize = 10000;
+/// Attempts the Postgres store makes before giving up on a contended bucket.
+const MAX_RETRIES: usize = 5;
+/// Seconds to ask clients to wait when a failing store refuses them.
+const STORE_RETRY_SECONDS: u64 = 30;
+
+
+/// Token bucket parameters: up to `capacity` requests at once, refilled at
+/// `per_second` tokens per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    pub capacity: f64,
+    pub per_second: f64,
+}
+
+impl Limit {
+    /// Parses `<requests>/<seconds>`, e.g. `10/60` for ten requests a minute.
+    pub fn parse(s: &str) -> Option<Limit> {
+        let mut parts = s.splitn(2, '/');
+        let requests = parts.next().and_then(|r| r.trim().parse::<u32>().ok());
+        let seconds = parts.next().and_then(|s| s.trim().parse::<u32>().ok());
+        match (requests, seconds) {
+            (Some(r), Some(s)) if r > 0 && s > 0 => Some(Limit { capacity: r as f64, per_second: r as f64 / s as f64 }),
+            _ => None
+        }
+    }
+
+    /// Refills a bucket last seen holding `tokens` at `updated` and takes one
+    /// token from it. Returns the new token count, or the seconds until a
+    /// token is available when it is empty.
+    pub fn take(&self, tokens: f64, updated: NaiveDateTime, now: NaiveDateTime) -> Result<f64, u64> {
+        let elapsed = now.signed_duration_since(updated).num_milliseconds().max(0) as f64 / 1000.0;
+        let tokens = (tokens + elapsed * self.per_second).min(self.capacity);
+        if tokens >= 1.0 {
+            Ok(tokens - 1.0)
+        } else {
+            Err(((1.0 - tokens) / self.per_second).ceil() as u64)
+        }
+    }
+}
+
+
+/// Where token buckets are kept.
+pub trait Store: Send + Sync {
+    /// Takes one token from bucket `key`; `Ok(Some(secs))` means it is empty
+    /// and the caller should retry after `secs` seconds.
+    fn take(&self, key: &str, limit: &Limit) -> DBResult<Option<u64>>;
+}
+
+
+/// Keeps buckets in this process; enough for a single instance.
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, (Limit, f64, NaiveDateTime)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore { buckets: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl Store for MemoryStore {
+    fn take(&self, key: &str, limit: &Limit) -> DBResult<Option<u64>> {
+        let now = UTC::now().naive_utc();
+        let mut buckets = self.buckets.lock().expect("Rate limit store poisoned.");
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, &mut (l, tokens, updated)| l.take(tokens, updated, now)
+                           .map_or(true, |left| left + 1.0 < l.capacity));
+        }
+
+        let (tokens, updated) = buckets.get(key).map_or((limit.capacity, now), |&(_, t, u)| (t, u));
+        match limit.take(tokens, updated, now) {
+            Ok(left) => {
+                buckets.insert(key.into(), (*limit, left, now));
+                Ok(None)
+            },
+            Err(wait) => Ok(Some(wait)),
+        }
+    }
+}
+
+
+/// Keeps buckets in the `rate_limits` table so that every instance sees them.
+pub struct PgStore;
+
+impl Store for PgStore {
+    fn take(&self, key: &str, limit: &Limit) -> DBResult<Option<u64>> {
+        let conn = DB_POOL.get()?;
+        for _ in 0..MAX_RETRIES {
+            let now = UTC::now().naive_utc();
+            let found = rate_limit::get(&*conn, key)?;
+            let (tokens, updated) = found.as_ref().map_or((limit.capacity, now), |b| (b.tokens, b.updated));
+            let left = match limit.take(tokens, updated, now) {
+                Ok(left) => left,
+                Err(wait) => return Ok(Some(wait)),
+            };
+            let stored = match found {
+                Some(ref bucket) => rate_limit::swap(&*conn, bucket, left, now)?,
+                None => rate_limit::create(&*conn, key, left, now)?,
+            };
+            if stored {
+                return Ok(None);
+            }
+        }
+        Err(Error::DatabaseError(format!("rate limit bucket {} is contended", key)))
+    }
+}
+
+
+/// Rate limits and the store they are enforced with.
+pub struct Limiter {
+    store: Box<Store>,
+    ip: Limit,
+    visitor: Limit,
+    mail: Limit,
+    ip_header: Option<String>,
+    trusted_proxies: usize,
+}
+
+impl Limiter {
+    /// Configures limits from the environment:
+    ///
+    /// * `RATE_LIMIT_STORE`: `memory` (default) or `postgres`
+    /// * `RATE_LIMIT_IP`, `RATE_LIMIT_VISITOR`, `RATE_LIMIT_MAIL`: `<requests>/<seconds>`
+    /// * `RATE_LIMIT_IP_HEADER`: header holding the client address behind a proxy
+    /// * `RATE_LIMIT_TRUSTED_PROXIES`: proxies that append to that header (default 1)
+    pub fn from_env() -> Limiter {
+        dotenv().ok();
+
+        let limit = |var: &str, default: &str| {
+            let value = env::var(var).unwrap_or(default.into());
+            Limit::parse(&value).expect(&format!("{} must look like <requests>/<seconds>", var))
+        };
+        let store: Box<Store> = match env::var("RATE_LIMIT_STORE").as_ref().map(|s| s.as_str()) {
+            Ok("postgres") => Box::new(PgStore),
+            Ok("memory") | Err(_) => Box::new(MemoryStore::new()),
+            Ok(s) => panic!("Unknown RATE_LIMIT_STORE: {}", s),
+        };
+        Limiter {
+            store: store,
+            ip: limit("RATE_LIMIT_IP", "10/60"),
+            visitor: limit("RATE_LIMIT_VISITOR", "5/60"),
+            mail: limit("RATE_LIMIT_MAIL", "3/3600"),
+            ip_header: env::var("RATE_LIMIT_IP_HEADER").ok(),
+            trusted_proxies: env::var("RATE_LIMIT_TRUSTED_PROXIES").ok()
+                .map(|n| match n.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => panic!("RATE_LIMIT_TRUSTED_PROXIES must be a positive number"),
+                })
+                .unwrap_or(1),
+        }
+    }
+
+    /// Takes a token from `key`. When the store fails the error is logged and
+    /// the request let through, unless `fail_closed`.
+    fn take(&self, key: &str, limit: &Limit, fail_closed: bool) -> Result<(), Limited> {
+        match self.store.take(key, limit) {
+            Ok(None) => Ok(()),
+            Ok(Some(wait)) => Err(Limited { retry_after: wait }),
+            Err(e) => {
+                println!("    => Rate limit store error: {}", e);
+                if fail_closed {
+                    Err(Limited { retry_after: STORE_RETRY_SECONDS })
+                } else {
+                    Ok(())
+                }
+            },
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref LIMITER: Limiter = Limiter::from_env();
+}
+
+
+/// A request was refused for exceeding a rate limit.
+#[derive(Debug)]
+pub struct Limited {
+    pub retry_after: u64,
+}
+
+
+/// A write endpoint whose requests are counted separately from the others.
+pub trait Action {
+    fn name() -> &'static str;
+
+    /// Whether to refuse requests while the store cannot be reached.
+    fn fail_closed() -> bool { false }
+}
+
+pub struct CommentCreate;
+
+impl Action for CommentCreate {
+    fn name() -> &'static str { "comment" }
+
+    fn fail_closed() -> bool { true }
+}
+
+pub struct VisitorCreate;
+
+impl Action for VisitorCreate {
+    fn name() -> &'static str { "visitor" }
+}
+
+
+/// Request guard identifying the client of action `A` for rate limiting.
+///
+/// Nothing is charged until the handler calls `check`, since the visitor id
+/// is only known once the body has been parsed.
+pub struct Throttle<A: Action> {
+    client: String,
+    _action: PhantomData<A>,
+}
+
+impl<A: Action> Throttle<A> {
+    /// Takes a token from the client's bucket.
+    pub fn check(&self) -> Result<(), Limited> {
+        LIMITER.take(&format!("{}:ip:{}", A::name(), self.client), &LIMITER.ip, A::fail_closed())
+    }
+
+    /// Takes a token from the bucket of visitor `vid`.
+    pub fn check_visitor(&self, vid: i32) -> Result<(), Limited> {
+        LIMITER.take(&format!("{}:visitor:{}", A::name(), vid), &LIMITER.visitor, A::fail_closed())
+    }
+
+    /// Takes a token from the bucket of mail sent to visitor `vid` on request.
+    pub fn check_mail(&self, vid: i32) -> Result<(), Limited> {
+        LIMITER.take(&format!("{}:mail:{}", A::name(), vid), &LIMITER.mail, A::fail_closed())
+    }
+}
+
+/// The client address in a forwarding header such as `X-Forwarded-For`.
+///
+/// Each proxy appends the address it was connected from, so only the entry
+/// added by the outermost of the `trusted` proxies can be believed; the ones
+/// before it are whatever the client sent.
+fn forwarded_client(header: &str, trusted: usize) -> Option<String> {
+    let entries: Vec<&str> = header.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()).collect();
+    if entries.is_empty() {
+        return None;
+    }
+    let index = entries.len().saturating_sub(trusted);
+    Some(entries[index].to_string())
+}
+
+impl<'a, 'r, A: Action> FromRequest<'a, 'r> for Throttle<A> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let forwarded = LIMITER.ip_header.as_ref()
+            .and_then(|h| request.headers().get_one(h))
+            .and_then(|v| forwarded_client(v, LIMITER.trusted_proxies));
+        let client = forwarded
+            .or(request.remote().map(|addr| addr.ip().to_string()))
+            .unwrap_or("unknown".into());
+        Success(Throttle { client: client, _action: PhantomData })
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_limit() {
+        let limit = Limit::parse("2/10").unwrap();
+        assert!(Limit::parse("2").is_none() && Limit::parse("0/10").is_none());
+
+        let start = NaiveDate::from_ymd(2017, 5, 1).and_hms(0, 0, 0);
+        let left = limit.take(limit.capacity, start, start).unwrap();
+        let left = limit.take(left, start, start).unwrap();
+        assert!(left == 0.0);
+        assert!(limit.take(left, start, start) == Err(5));
+        let later = NaiveDate::from_ymd(2017, 5, 1).and_hms(0, 0, 5);
+        assert!(limit.take(left, start, later) == Ok(0.0));
+
+        let store = MemoryStore::new();
+        assert!(store.take("a", &limit).unwrap().is_none());
+        assert!(store.take("a", &limit).unwrap().is_none());
+        assert!(store.take("a", &limit).unwrap() == Some(5));
+        assert!(store.take("b", &limit).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_forwarded_client() {
+        assert!(forwarded_client("203.0.113.9", 1) == Some("203.0.113.9".into()));
+        assert!(forwarded_client("1.2.3.4, 203.0.113.9", 1) == Some("203.0.113.9".into()));
+        assert!(forwarded_client("1.2.3.4, 203.0.113.9, 10.0.0.2", 2) == Some("203.0.113.9".into()));
+        assert!(forwarded_client("203.0.113.9", 3) == Some("203.0.113.9".into()));
+        assert!(forwarded_client(" , ", 1).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+// Signing
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+
+// Environment
+use dotenv::dotenv;
+use std::env;
+
+// Timestamp
+use chrono::prelude::*;
+use chrono::Duration;
+
+
+lazy_static! {
+    static ref SECRET_KEY: String = {
+        dotenv().ok();
+        env::var("SECRET_KEY").expect("SECRET_KEY must be set")
+    };
+}
+
+/// The `SECRET_KEY` shipped in `.env`, which must never sign anything.
+const PLACEHOLDER_SECRET: &'static str = "change-me-in-production";
+
+
+/// Stops the server on launch unless a real `SECRET_KEY` is set.
+pub fn check_secret() {
+    if SECRET_KEY.trim().is_empty() || SECRET_KEY.as_str() == PLACEHOLDER_SECRET {
+        panic!("SECRET_KEY must be set to a random value of your own");
+    }
+}
+
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TokenError {
+    Invalid,
+    Expired,
+}
+
+
+fn mac(secret: &str, purpose: &str, id: i32, expires: i64, binding: &str) -> String {
+    let mut hmac = Hmac::new(Sha256::new(), secret.as_bytes());
+    hmac.input(format!("{}\n{}\n{}\n{}", purpose, id, expires, binding).as_bytes());
+    hmac.result().code().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+fn sign_with(secret: &str, purpose: &str, id: i32, binding: &str, expires: NaiveDateTime) -> String {
+    let expires = expires.timestamp();
+    format!("{}.{}.{}", id, expires, mac(secret, purpose, id, expires, binding))
+}
+
+
+fn verify_with(secret: &str, purpose: &str, token: &str, binding: &str, now: NaiveDateTime) -> Result<i32, TokenError> {
+    let parts: Vec<&str> = token.split('.').collect();
+    if parts.len() != 3 {
+        return Err(TokenError::Invalid);
+    }
+    let (id, expires) = match (parts[0].parse::<i32>(), parts[1].parse::<i64>()) {
+        (Ok(id), Ok(expires)) => (id, expires),
+        _ => return Err(TokenError::Invalid),
+    };
+    let expected = mac(secret, purpose, id, expires, binding);
+    if !fixed_time_eq(expected.as_bytes(), parts[2].as_bytes()) {
+        return Err(TokenError::Invalid);
+    }
+    if now.timestamp() > expires {
+        return Err(TokenError::Expired);
+    }
+    Ok(id)
+}
+
+
+/// Signs a token for record `id` that is valid for `purpose` until `ttl` has passed.
+///
+/// `binding` is covered by the signature without being part of the token, so
+/// the token stops verifying once it changes (e.g. a visitor's address).
+pub fn sign(purpose: &str, id: i32, binding: &str, ttl: Duration) -> String {
+    sign_with(&SECRET_KEY, purpose, id, binding, UTC::now().naive_utc() + ttl)
+}
+
+
+/// Record id a token claims to be for, before it has been verified.
+pub fn peek(token: &str) -> Option<i32> {
+    token.split('.').next().and_then(|id| id.parse().ok())
+}
+
+
+/// Checks a token made by `sign` and returns the record id it was signed for.
+pub fn verify(purpose: &str, token: &str, binding: &str) -> Result<i32, TokenError> {
+    verify_with(&SECRET_KEY, purpose, token, binding, UTC::now().naive_utc())
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token() {
+        let now = NaiveDate::from_ymd(2017, 6, 1).and_hms(12, 0, 0);
+        let token = sign_with("secret", "verify", 42, "a@b.c", now + Duration::hours(1));
+
+        assert!(peek(&token) == Some(42));
+        assert!(verify_with("secret", "verify", &token, "a@b.c", now) == Ok(42));
+        assert!(verify_with("secret", "verify", &token, "x@b.c", now) == Err(TokenError::Invalid));
+        assert!(verify_with("secret", "other", &token, "a@b.c", now) == Err(TokenError::Invalid));
+        assert!(verify_with("other", "verify", &token, "a@b.c", now) == Err(TokenError::Invalid));
+        assert!(verify_with("secret", "verify", &token.replace("42.", "43."), "a@b.c", now) == Err(TokenError::Invalid));
+        assert!(verify_with("secret", "verify", &token, "a@b.c", now + Duration::hours(2)) == Err(TokenError::Expired));
+    }
+}